@@ -0,0 +1,116 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, format_err};
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::property;
+use crate::temporal::{is_cut, reduce_frames};
+
+/*
+A filter can't request its own output frames, so the recursive modes can't literally use output n - 1.
+Instead, every frame is rebuilt from up to `depth` previous input frames, which is exactly what the recursion
+would give if it was (re)started `depth` frames earlier. Since that only depends on the input, seeking to any
+frame gives the same result as rendering the clip in order.
+*/
+
+#[derive(Clone, Copy)]
+pub enum Accumulation {
+    // exponential moving average, output n = alpha * input n + (1 - alpha) * output n - 1
+    Ema(f64),
+    // mean of every frame so far
+    Cumulative,
+}
+
+pub struct Accumulate<'core> {
+    pub clip: Node<'core>,
+    pub mode: Accumulation,
+    // maximum number of previous frames used for a frame, where the recursion (re)starts.
+    // `None` goes all the way back to the first frame (or the last scene change).
+    pub depth: Option<usize>,
+    // (re)start the recursion at scene changes
+    pub scene_change: bool,
+    // see `Temporal`
+    pub threshold: f64,
+}
+
+impl<'core> Accumulate<'core> {
+    // oldest frame that might be used for frame `n`, before taking scene changes into account
+    fn start(&self, n: usize) -> usize {
+        self.depth.map_or(0, |depth| n.saturating_sub(depth))
+    }
+
+    // Weights of the frames `start..=n`, oldest first. The oldest frame is where the recursion starts,
+    // so it takes the place of output `start - 1`, and gets all of the remaining weight.
+    fn weights(&self, len: usize) -> Option<Vec<f64>> {
+        match self.mode {
+            Accumulation::Ema(alpha) => {
+                let mut weights = (0..len)
+                    .rev()
+                    .map(|age| alpha * (1.0 - alpha).powi(age as i32))
+                    .collect::<Vec<_>>();
+                weights[0] = (1.0 - alpha).powi(len as i32 - 1);
+                Some(weights)
+            },
+            Accumulation::Cumulative => None,
+        }
+    }
+}
+
+impl<'core> Filter<'core> for Accumulate<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clip.info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        // every frame up to `depth` back might be needed, as the scene changes aren't known yet
+        for m in self.start(n)..=n {
+            self.clip.request_frame_filter(context, m);
+        }
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clip.info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let mut start = self.start(n);
+        let mut src_frames = (start..=n)
+            .map(|m| self.clip.get_frame_filter(context, m).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // only the frames since the last scene change
+        if self.scene_change {
+            let mut first = n;
+            while first > start && !is_cut(&src_frames[first - 1 - start], &src_frames[first - start], self.threshold)? {
+                first -= 1;
+            }
+            src_frames.drain(..first - start);
+            start = first;
+        }
+
+        let weights = self.weights(n - start + 1);
+
+        let prop_src = Some(&*src_frames[n - start]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        reduce_frames(&mut out_frame, &src_frames, weights.as_deref(), Reducer::Mean)?;
+
+        Ok(out_frame.into())
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+// same block size as the (luma) transform of most codecs
+pub const BLOCK: usize = 8;
+
+pub struct BlockDct<'core> {
+    pub clips: Vec<Node<'core>>,
+    pub reducer: Reducer,
+    // distance between the top left corners of neighbouring blocks, `BLOCK` for no overlap
+    pub step: usize,
+    // orthonormal DCT-II basis, `basis[k][i]` being frequency `k` at position `i`
+    pub basis: [[f64; BLOCK]; BLOCK],
+}
+
+impl<'core> BlockDct<'core> {
+    pub fn new(clips: Vec<Node<'core>>, reducer: Reducer, overlap: usize) -> Self {
+        let mut basis = [[0.0; BLOCK]; BLOCK];
+        for (k, row) in basis.iter_mut().enumerate() {
+            let scale = if k == 0 { (1.0 / BLOCK as f64).sqrt() } else { (2.0 / BLOCK as f64).sqrt() };
+            for (i, v) in row.iter_mut().enumerate() {
+                *v = scale * (std::f64::consts::PI * (2 * i + 1) as f64 * k as f64 / (2 * BLOCK) as f64).cos();
+            }
+        }
+        Self { clips, reducer, step: BLOCK - overlap, basis }
+    }
+
+    // Top left corners of the blocks along one dimension. The last block is moved back so it ends at the edge.
+    fn positions(&self, len: usize) -> Vec<usize> {
+        let mut positions = (0..).step_by(self.step).take_while(|p| p + BLOCK < len).collect::<Vec<_>>();
+        positions.push(len.saturating_sub(BLOCK));
+        positions
+    }
+
+    // 2D transform of a block, separable into one over the rows and one over the columns
+    fn transform(&self, block: &mut [[f64; BLOCK]; BLOCK], inverse: bool) {
+        let basis = &self.basis;
+        let multiply = |a: usize, b: usize| if inverse { basis[b][a] } else { basis[a][b] };
+
+        let mut temp = [[0.0; BLOCK]; BLOCK];
+        for (y, row) in temp.iter_mut().enumerate() {
+            for (x, v) in row.iter_mut().enumerate() {
+                *v = (0..BLOCK).map(|i| multiply(x, i) * block[y][i]).sum();
+            }
+        }
+        for (y, row) in block.iter_mut().enumerate() {
+            for (x, v) in row.iter_mut().enumerate() {
+                *v = (0..BLOCK).map(|i| multiply(y, i) * temp[i][x]).sum();
+            }
+        }
+    }
+
+    // Transforms co-located blocks of every source, combines them coefficient wise, and transforms back.
+    // Overlapping blocks are averaged. `range` is what the output gets clamped to.
+    pub fn block_dct<T: F64Convertible>(&self, out_frame: &mut FrameRefMut, src_frames: &[FrameRef], range: (f64, f64)) {
+        let clips = src_frames.len();
+
+        let mut blocks = vec![[[0.0; BLOCK]; BLOCK]; clips];
+        let mut values = Vec::with_capacity(clips);
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+
+        for plane in 0..format.plane_count() {
+            let (width, height) = (out_frame.width(plane), out_frame.height(plane));
+
+            let mut sum = vec![0.0; width * height];
+            let mut count = vec![0u32; width * height];
+
+            let columns = self.positions(width);
+            for by in self.positions(height) {
+                // planes smaller than a block are mirrored to fill it
+                let rows = (0..BLOCK)
+                    .map(|y| mirror((by + y) as isize, height))
+                    .collect::<Vec<_>>();
+
+                for &bx in &columns {
+                    for (f, block) in src_frames.iter().zip(blocks.iter_mut()) {
+                        for (&y, block_row) in rows.iter().zip(block.iter_mut()) {
+                            let row = f.plane_row::<T>(plane, y);
+                            for (x, v) in block_row.iter_mut().enumerate() {
+                                *v = row[mirror((bx + x) as isize, width)].to_f64();
+                            }
+                        }
+                        self.transform(block, false);
+                    }
+
+                    // the first block holds the combined coefficients
+                    for y in 0..BLOCK {
+                        for x in 0..BLOCK {
+                            // Vec reuse: filling
+                            values.extend(blocks.iter().map(|b| b[y][x]));
+                            blocks[0][y][x] = self.reducer.reduce(&mut values);
+                            // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                            unsafe { values.set_len(0); }
+                        }
+                    }
+                    self.transform(&mut blocks[0], true);
+
+                    for (y, block_row) in blocks[0].iter().enumerate().take(height - by) {
+                        for (x, v) in block_row.iter().enumerate().take(width - bx) {
+                            let index = (by + y) * width + bx + x;
+                            sum[index] += v;
+                            count[index] += 1;
+                        }
+                    }
+                }
+            }
+
+            for (row, (sum, count)) in sum.chunks_exact(width).zip(count.chunks_exact(width)).enumerate() {
+                for ((pixel, sum), &count) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().zip(sum).zip(count) {
+                    let value = (sum / count as f64).max(range.0).min(range.1);
+                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(value)) }
+                }
+            }
+        }
+    }
+}
+
+impl<'core> Filter<'core> for BlockDct<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        // the inverse transform can overshoot, which would wrap around for integer formats
+        let range = match format.sample_type() {
+            SampleType::Integer => (0.0, ((1u64 << format.bits_per_sample()) - 1) as f64),
+            SampleType::Float => (f64::NEG_INFINITY, f64::INFINITY),
+        };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => self.block_dct::<u8> (&mut out_frame, &src_frames, range),
+            (SampleType::Integer,  9..=16) => self.block_dct::<u16>(&mut out_frame, &src_frames, range),
+            (SampleType::Integer, 17..=32) => self.block_dct::<u32>(&mut out_frame, &src_frames, range),
+            (SampleType::Float,        16) => self.block_dct::<f16>(&mut out_frame, &src_frames, range),
+            (SampleType::Float,        32) => self.block_dct::<f32>(&mut out_frame, &src_frames, range),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
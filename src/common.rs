@@ -4,6 +4,7 @@
 
 use half::f16;
 use vapoursynth::component::Component;
+use vapoursynth::prelude::*;
 
 // Conversion trait to and from f64
 
@@ -73,16 +74,245 @@ impl F64Convertible for f32 {
     }
 }
 
+// Per frame weights, from the per clip frame type multipliers and the static per clip weights.
+// Returns `None` if there is no weighting at all.
+pub fn frame_weights(src_frames: &[FrameRef], multipliers: Option<&[[f64; 3]]>, clip_weights: Option<&[f64]>) -> Option<Vec<f64>> {
+    if multipliers.is_none() && clip_weights.is_none() {
+        return None;
+    }
+
+    let weights: Vec<_> = src_frames
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let multiplier = match multipliers {
+                Some(multipliers) => match f.props().get::<&'_ [u8]>("_PictType").unwrap_or(b"U")[0] {
+                    b'I' | b'i' => multipliers[i][0],
+                    b'P' | b'p' => multipliers[i][1],
+                    b'B' => multipliers[i][2],
+                    _ => 1.0,
+                },
+                None => 1.0,
+            };
+            multiplier * clip_weights.map_or(1.0, |w| w[i])
+        })
+        .collect();
+
+    Some(weights)
+}
+
+// Sets each pixel of `out_frame` to the reduction of the co-located pixels of `src_frames`, calculated in f64.
+// `reduce` is free to reorder or remove the values it is given.
+pub fn reduce_pixels<T: F64Convertible>(
+    out_frame: &mut FrameRefMut,
+    src_frames: &[FrameRef],
+    mut reduce: impl FnMut(&mut Vec<f64>) -> f64,
+) {
+    // See note on reusing vecs in mean.rs
+    let mut src_rows = Vec::with_capacity(src_frames.len());
+    let mut values = Vec::with_capacity(src_frames.len());
+
+    // `out_frame` has the same format as the input clips
+    let format = out_frame.format();
+
+    for plane in 0..format.plane_count() {
+        for row in 0..out_frame.height(plane) {
+            // Vec reuse: filling
+            src_rows.extend(src_frames
+                .iter()
+                .map(|f| f.plane_row::<T>(plane, row)));
+            for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                // Vec reuse: filling
+                values.extend(src_rows
+                    .iter()
+                    .map(|f| f[i].to_f64()));
+                let data = reduce(&mut values);
+                unsafe { std::ptr::write(pixel, F64Convertible::from_f64(data)) }
+                // `reduce` may have removed values, so this has to be a regular clear
+                values.clear();
+            }
+            // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+            unsafe { src_rows.set_len(0); }
+        }
+    }
+}
+
+// Weighted mean of (value, weight) pairs. If all weights are zero, it falls back to the unweighted mean.
+pub fn weighted_average(values: &[(f64, f64)]) -> f64 {
+    let (weighted_sum, weight_sum) = values.iter().fold((0.0, 0.0), |(s, t), (p, w)| (s + p * w, t + w));
+    if weight_sum > 0.0 {
+        weighted_sum / weight_sum
+    } else {
+        values.iter().map(|(p, _)| p).sum::<f64>() / values.len() as f64
+    }
+}
+
+// Decides which sources to use on luma, and then uses the same sources for chroma, so that the chroma of a pixel
+// isn't drawn from a different mix of sources than its luma. Only makes sense for YUV.
+// `select` gets (value, clip index) pairs of a luma pixel, and has to move the `count` pairs to use to the front.
+// Each chroma pixel then uses the `count` sources that were picked most often for the luma pixels it covers.
+// The output is the weighted mean of the values of the picked sources.
+pub fn joint_luma_chroma<T: F64Convertible>(
+    out_frame: &mut FrameRefMut,
+    src_frames: &[FrameRef],
+    weights: Option<&[f64]>,
+    count: usize,
+    mut select: impl FnMut(&mut [(f64, usize)]),
+) {
+    let clips = src_frames.len();
+    let weight = |clip: usize| weights.map_or(1.0, |w| w[clip]);
+
+    // `out_frame` has the same format as the input clips
+    let format = out_frame.format();
+    let (ssw, ssh) = (format.sub_sampling_w(), format.sub_sampling_h());
+
+    // See note on reusing vecs in mean.rs
+    let mut src_rows = Vec::with_capacity(clips);
+    let mut pairs = Vec::with_capacity(clips);
+    let mut picked = Vec::with_capacity(count);
+    let mut ranked = Vec::with_capacity(clips);
+    // how often each source was picked for the luma pixels covered by each chroma pixel of the current row,
+    // indexed by `chroma pixel * clips + clip`
+    let mut votes = vec![0u32; out_frame.width(1) * clips];
+
+    for chroma_row in 0..out_frame.height(1) {
+        for row in (chroma_row << ssh)..((chroma_row + 1) << ssh) {
+            // Vec reuse: filling
+            src_rows.extend(src_frames
+                .iter()
+                .map(|f| f.plane_row::<T>(0, row)));
+            for (i, pixel) in out_frame.plane_row_mut::<T>(0, row).iter_mut().enumerate() {
+                // Vec reuse: filling
+                pairs.extend(src_rows
+                    .iter()
+                    .map(|f| f[i].to_f64())
+                    .zip(0..));
+                select(&mut pairs);
+
+                picked.extend(pairs[..count].iter().map(|&(p, clip)| (p, weight(clip))));
+                unsafe { std::ptr::write(pixel, F64Convertible::from_f64(weighted_average(&picked))) }
+                pairs[..count].iter().for_each(|&(_, clip)| votes[(i >> ssw) * clips + clip] += 1);
+
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                unsafe { pairs.set_len(0); }
+                unsafe { picked.set_len(0); }
+            }
+            // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+            unsafe { src_rows.set_len(0); }
+        }
+
+        for plane in 1..format.plane_count() {
+            // Vec reuse: filling
+            src_rows.extend(src_frames
+                .iter()
+                .map(|f| f.plane_row::<T>(plane, chroma_row)));
+            for (i, pixel) in out_frame.plane_row_mut::<T>(plane, chroma_row).iter_mut().enumerate() {
+                let votes = &votes[i * clips..(i + 1) * clips];
+                // Vec reuse: filling
+                ranked.extend(0..clips);
+                // stable, so ties go to the lower clip index
+                ranked.sort_by_key(|&clip| std::cmp::Reverse(votes[clip]));
+
+                picked.extend(ranked[..count].iter().map(|&clip| (src_rows[clip][i].to_f64(), weight(clip))));
+                unsafe { std::ptr::write(pixel, F64Convertible::from_f64(weighted_average(&picked))) }
+
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                unsafe { ranked.set_len(0); }
+                unsafe { picked.set_len(0); }
+            }
+            // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+            unsafe { src_rows.set_len(0); }
+        }
+
+        votes.iter_mut().for_each(|v| *v = 0);
+    }
+}
+
+// how a set of values is combined into one, for the filters that offer a choice
+#[derive(Clone, Copy)]
+pub enum Reducer {
+    Mean,
+    Median,
+    // mean, after discarding this many of the lowest and highest values
+    Trimmed(usize),
+}
+
+impl Reducer {
+    pub fn reduce(self, values: &mut [f64]) -> f64 {
+        match self {
+            Reducer::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Reducer::Median => median(values),
+            Reducer::Trimmed(discard) => {
+                let survivors = values.len() - discard * 2;
+                unsafe { ultra_pepega(values, discard, discard); }
+                values[..survivors].iter().sum::<f64>() / survivors as f64
+            },
+        }
+    }
+}
+
+// Mirrors an out of bounds index back into `0..len`, without repeating the edge. Planes smaller than the window are clamped.
+#[inline]
+pub fn mirror(i: isize, len: usize) -> usize {
+    let last = len as isize - 1;
+    let i = if i < 0 { -i } else if i > last { 2 * last - i } else { i };
+    i.max(0).min(last) as usize
+}
+
+// Blurs a plane in place with a (2 * radius + 1)² box, mirroring at the edges
+pub fn box_blur(plane: &mut [f64], width: usize, height: usize, radius: usize) {
+    let scale = 1.0 / (2 * radius + 1) as f64;
+    let window = |i: usize| (i as isize - radius as isize)..=(i as isize + radius as isize);
+    // copy of the current line, so that we don't read values we've already blurred
+    let mut line = Vec::with_capacity(width.max(height));
+
+    // horizontal
+    for row in plane.chunks_exact_mut(width) {
+        line.extend_from_slice(row);
+        for (x, out) in row.iter_mut().enumerate() {
+            *out = window(x).map(|i| line[mirror(i, width)]).sum::<f64>() * scale;
+        }
+        line.clear();
+    }
+
+    // vertical
+    for x in 0..width {
+        line.extend((0..height).map(|y| plane[y * width + x]));
+        for y in 0..height {
+            plane[y * width + x] = window(y).map(|i| line[mirror(i, height)]).sum::<f64>() * scale;
+        }
+        line.clear();
+    }
+}
+
+// median of some floats, which get sorted in the process
+pub fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    if values.len() & 1 == 1 {
+        values[(values.len() - 1) >> 1]
+    } else {
+        let middle = values.len() >> 1;
+        (values[middle - 1] + values[middle]) / 2.0
+    }
+}
+
+// minimum and maximum of some floats, ignoring NaNs
+pub fn min_max(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)))
+}
+
 pub unsafe fn swap<T>(slice: &mut [T], a: usize, b: usize) {
     let pa: *mut T = slice.get_unchecked_mut(a);
     let pb: *mut T = slice.get_unchecked_mut(b);
     std::ptr::swap(pa, pb);
 }
 
-pub unsafe fn ultra_pepega<T: PartialOrd>(a: &mut [T], n: usize) {
+// Moves the `high` largest values to the end of `a`, and the `low` smallest values right before them.
+// The remaining values end up in `a[..a.len() - low - high]`.
+pub unsafe fn ultra_pepega<T: PartialOrd>(a: &mut [T], low: usize, high: usize) {
     let len = a.len();
     // max
-    for i in 0..n {
+    for i in 0..high {
         let mut index = 0;
         for j in 0..len - i {
             if *a.get_unchecked(j) > *a.get_unchecked(index) {
@@ -92,8 +322,8 @@ pub unsafe fn ultra_pepega<T: PartialOrd>(a: &mut [T], n: usize) {
         swap(a, index, len - (i + 1));
     }
     // min
-    let len = a.len() - n;
-    for i in 0..n {
+    let len = a.len() - high;
+    for i in 0..low {
         let mut index = 0;
         for j in 0..len - i {
             if *a.get_unchecked(j) < *a.get_unchecked(index) {
@@ -0,0 +1,100 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+pub struct Consensus<'core> {
+    pub clips: Vec<Node<'core>>,
+    // maximum difference between any two values of a group
+    pub tolerance: f64,
+    // clip whose group wins when multiple groups are equally large
+    pub preferred: usize,
+}
+
+impl<'core> Consensus<'core> {
+    pub fn consensus<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], tolerance: f64, preferred: usize) {
+        // (value, clip index) pairs, so that we still know where a value came from after sorting
+        let mut indexed = Vec::with_capacity(src_frames.len());
+
+        reduce_pixels::<T>(out_frame, src_frames, |values| {
+            indexed.extend(values.iter().copied().zip(0..));
+            indexed.sort_unstable_by(|a: &(f64, usize), b| a.0.partial_cmp(&b.0).unwrap());
+
+            // Once sorted, every group is a window of consecutive values, whose first and last values are within tolerance.
+            // Find the largest one, preferring those that contain the preferred clip.
+            let (mut best_start, mut best_end, mut best_preferred) = (0, 1, false);
+            let mut end = 0;
+            for start in 0..indexed.len() {
+                while end < indexed.len() && indexed[end].0 - indexed[start].0 <= tolerance {
+                    end += 1;
+                }
+                let size = end - start;
+                let has_preferred = indexed[start..end].iter().any(|&(_, clip)| clip == preferred);
+                let best_size = best_end - best_start;
+                if size > best_size || (size == best_size && has_preferred && !best_preferred) {
+                    best_start = start;
+                    best_end = end;
+                    best_preferred = has_preferred;
+                }
+            }
+
+            let mean = indexed[best_start..best_end].iter().map(|(p, _)| p).sum::<f64>() / (best_end - best_start) as f64;
+            indexed.clear();
+            mean
+        });
+    }
+}
+
+impl<'core> Filter<'core> for Consensus<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Self::consensus::<u8> (&mut out_frame, &src_frames, self.tolerance, self.preferred),
+            (SampleType::Integer,  9..=16) => Self::consensus::<u16>(&mut out_frame, &src_frames, self.tolerance, self.preferred),
+            (SampleType::Integer, 17..=32) => Self::consensus::<u32>(&mut out_frame, &src_frames, self.tolerance, self.preferred),
+            (SampleType::Float,        16) => Self::consensus::<f16>(&mut out_frame, &src_frames, self.tolerance, self.preferred),
+            (SampleType::Float,        32) => Self::consensus::<f32>(&mut out_frame, &src_frames, self.tolerance, self.preferred),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+#[derive(Clone, Copy)]
+pub enum Extremum {
+    Min,
+    Max,
+    // max - min, a cheap measure of how much the clips disagree
+    Range,
+}
+
+pub struct Extrema<'core> {
+    pub clips: Vec<Node<'core>>,
+    pub extremum: Extremum,
+}
+
+impl<'core> Extrema<'core> {
+    pub fn extrema<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], extremum: Extremum) {
+        reduce_pixels::<T>(out_frame, src_frames, |values| {
+            let (min, max) = min_max(values.iter().copied());
+            match extremum {
+                Extremum::Min => min,
+                Extremum::Max => max,
+                Extremum::Range => max - min,
+            }
+        });
+    }
+}
+
+impl<'core> Filter<'core> for Extrema<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Self::extrema::<u8> (&mut out_frame, &src_frames, self.extremum),
+            (SampleType::Integer,  9..=16) => Self::extrema::<u16>(&mut out_frame, &src_frames, self.extremum),
+            (SampleType::Integer, 17..=32) => Self::extrema::<u32>(&mut out_frame, &src_frames, self.extremum),
+            (SampleType::Float,        16) => Self::extrema::<f16>(&mut out_frame, &src_frames, self.extremum),
+            (SampleType::Float,        32) => Self::extrema::<f32>(&mut out_frame, &src_frames, self.extremum),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+pub struct FrequencySplit<'core> {
+    pub clips: Vec<Node<'core>>,
+    // radius of the box blur that separates the low from the high frequencies
+    pub radius: usize,
+    pub low: Reducer,
+    pub high: Reducer,
+}
+
+impl<'core> FrequencySplit<'core> {
+    // Splits every source into a low (blurred) and high (source - blurred) frequency band,
+    // combines each band with its own reducer, and adds the results back together.
+    pub fn frequency_split<T: F64Convertible>(&self, out_frame: &mut FrameRefMut, src_frames: &[FrameRef]) {
+        let clips = src_frames.len();
+
+        // See note on reusing vecs in mean.rs
+        let mut src_rows = Vec::with_capacity(clips);
+        let mut values = Vec::with_capacity(clips);
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+
+        for plane in 0..format.plane_count() {
+            let (width, height) = (out_frame.width(plane), out_frame.height(plane));
+            let size = width * height;
+
+            // low band of every clip, for the whole plane since the blur needs the neighbouring rows
+            let mut lows = vec![0.0; clips * size];
+            for (f, low) in src_frames.iter().zip(lows.chunks_exact_mut(size)) {
+                for (row, low) in low.chunks_exact_mut(width).enumerate() {
+                    for (v, p) in low.iter_mut().zip(f.plane_row::<T>(plane, row)) {
+                        *v = p.to_f64();
+                    }
+                }
+                box_blur(low, width, height, self.radius);
+            }
+
+            for row in 0..height {
+                // Vec reuse: filling
+                src_rows.extend(src_frames
+                    .iter()
+                    .map(|f| f.plane_row::<T>(plane, row)));
+                for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                    let index = row * width + i;
+
+                    // Vec reuse: filling
+                    values.extend((0..clips).map(|clip| lows[clip * size + index]));
+                    let low = self.low.reduce(&mut values);
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                    unsafe { values.set_len(0); }
+
+                    // Vec reuse: filling
+                    values.extend(src_rows
+                        .iter()
+                        .enumerate()
+                        .map(|(clip, f)| f[i].to_f64() - lows[clip * size + index]));
+                    let high = self.high.reduce(&mut values);
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                    unsafe { values.set_len(0); }
+
+                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(low + high)) }
+                }
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                unsafe { src_rows.set_len(0); }
+            }
+        }
+    }
+}
+
+impl<'core> Filter<'core> for FrequencySplit<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => self.frequency_split::<u8> (&mut out_frame, &src_frames),
+            (SampleType::Integer,  9..=16) => self.frequency_split::<u16>(&mut out_frame, &src_frames),
+            (SampleType::Integer, 17..=32) => self.frequency_split::<u32>(&mut out_frame, &src_frames),
+            (SampleType::Float,        16) => self.frequency_split::<f16>(&mut out_frame, &src_frames),
+            (SampleType::Float,        32) => self.frequency_split::<f32>(&mut out_frame, &src_frames),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
@@ -4,25 +4,53 @@
 //!
 //! A VapourSynth plugin for averaging clips together
 
+mod accumulate;
+mod block_dct;
+mod consensus;
+mod extrema;
+mod frequency_split;
 mod mean;
 mod median;
+mod mode;
+mod patch_mean;
+mod percentile;
+mod sigma_clip;
+mod stack;
+mod stats;
+mod temporal;
+mod window_median;
 mod common;
 
-use failure::{Error, bail, ensure};
+use failure::{Error, bail, ensure, format_err};
 use vapoursynth::prelude::*;
 use vapoursynth::core::CoreRef;
+use vapoursynth::format::Format;
 use vapoursynth::map::ValueIter;
 use vapoursynth::plugins::{Filter, FilterArgument, Metadata};
 use vapoursynth::video_info::Property::Constant;
 use vapoursynth::{make_filter_function, export_vapoursynth_plugin};
-use self::mean::Mean;
-use self::median::Median;
+use self::accumulate::{Accumulate, Accumulation};
+use self::block_dct::{BLOCK, BlockDct};
+use self::consensus::Consensus;
+use self::extrema::{Extrema, Extremum};
+use self::common::Reducer;
+use self::frequency_split::FrequencySplit;
+use self::mean::{Discard, Estimator, Mean, MeanKind, Robust};
+use self::median::{Median, MedianMode};
+use self::mode::{Mode, Tie};
+use self::patch_mean::{PatchMean, Reference};
+use self::percentile::Percentile;
+use self::sigma_clip::{Center, SigmaClip};
+use self::stack::{Shutter, Stack};
+use self::stats::Stats;
+use self::temporal::{Boundary, Temporal};
+use self::window_median::WindowMedian;
 
 pub const PLUGIN_NAME: &str = "vs-average";
 pub const PLUGIN_IDENTIFIER: &str = "eoe-nephren.average";
 
 fn check_clips<'core>(clips: &[Node<'core>]) -> Result<(), Error> {
-    ensure!(clips.len() > 0, "There should be at least one clip as input");
+    ensure!(!clips.is_empty(), "There should be at least one clip as input");
     if !clips.iter()
         .map(|s| s.info())
         .all(|i| matches!((i.format, i.framerate, i.resolution), (Constant(_), Constant(_), Constant(_))))
@@ -42,6 +70,168 @@ fn check_clips<'core>(clips: &[Node<'core>]) -> Result<(), Error> {
     Ok(())
 }
 
+// broadcasts a single value to every clip, or checks that there is exactly one value per clip
+fn per_clip<T: Copy>(values: Vec<T>, num_clips: usize, name: &str) -> Result<Vec<T>, Error> {
+    match values.len() {
+        1 => Ok(vec![values[0]; num_clips]),
+        n if n == num_clips => Ok(values),
+        _ => bail!("{} must contain either a single value, or exactly one value per clip!", name),
+    }
+}
+
+// static per clip weights, combined with the frame type weighting at runtime
+fn clip_weights(weights: Option<ValueIter<'_, '_, f64>>, num_clips: usize) -> Result<Option<Vec<f64>>, Error> {
+    match weights {
+        Some(weights) => {
+            let weights = weights.collect::<Vec<_>>();
+            ensure!(weights.len() == num_clips, "weights must contain exactly one value per clip!");
+            ensure!(weights.iter().all(|&w| w >= 0.0), "weights cannot be negative!");
+            ensure!(weights.iter().any(|&w| w > 0.0), "At least one weight must be greater than zero!");
+            Ok(Some(weights))
+        },
+        None => Ok(None),
+    }
+}
+
+// per clip frame type multipliers, either from presets or from the encoder's ip/pb ratios
+fn frame_type_weights(
+    preset: Option<ValueIter<'_, '_, i64>>,
+    ipratio: Option<ValueIter<'_, '_, f64>>,
+    pbratio: Option<ValueIter<'_, '_, f64>>,
+    num_clips: usize,
+) -> Result<Option<Vec<[f64; 3]>>, Error> {
+    let weights = match (preset, ipratio, pbratio) {
+        (None, None, None) => None,
+        (Some(preset), None, None) => {
+            let weights = per_clip(preset.collect(), num_clips, "preset")?
+                .into_iter()
+                .map(|p| match p {
+                    0 => Ok([1.00, 1.00, 1.00]), // balanced
+                    1 => Ok([1.82, 1.30, 1.00]), // x264/5 defaults    (IP = 1.4, PB = 1.3)
+                    2 => Ok([1.21, 1.10, 1.00]), // x264 `--tune grain` (IP = 1.1, PB = 1.1)
+                    3 => Ok([1.10, 1.00, 1.00]), // x265 `--tune grain` (IP = 1.1, PB = 1.0)
+                    _ => bail!("Unknown preset! (Only 0..3 supported, see docs for more information)"),
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            // all balanced is the same as no weighting at all, so we can skip it entirely
+            if weights.iter().all(|w| *w == [1.00, 1.00, 1.00]) { None } else { Some(weights) }
+        },
+        (None, ipratio, pbratio) => {
+            let ipratio = per_clip(ipratio.map_or_else(|| vec![1.0], |r| r.collect()), num_clips, "ipratio")?;
+            let pbratio = per_clip(pbratio.map_or_else(|| vec![1.0], |r| r.collect()), num_clips, "pbratio")?;
+            ensure!(ipratio.iter().chain(pbratio.iter()).all(|&r| r > 0.0), "ipratio and pbratio must be greater than zero!");
+            Some(ipratio.into_iter().zip(pbratio).map(|(ip, pb)| [ip * pb, pb, 1.0]).collect())
+        },
+        (Some(_), _, _) => bail!("preset cannot be used together with ipratio or pbratio!"),
+    };
+
+    Ok(weights)
+}
+
+// parses a 0/1 flag, and checks that it's only enabled for YUV clips
+fn joint_flag(joint: Option<i64>, format: Format) -> Result<bool, Error> {
+    let joint = match joint {
+        None | Some(0) => false,
+        Some(1) => true,
+        Some(_) => bail!("joint must be either 0 or 1!"),
+    };
+    ensure!(!joint || format.color_family() == ColorFamily::YUV, "joint can only be used with YUV clips!");
+    Ok(joint)
+}
+
+// resolves a discard argument to a number of clips. Values below 1 are a fraction of the number of clips
+fn discard_count(discard: f64, num_clips: usize, name: &str) -> Result<usize, Error> {
+    ensure!(discard >= 0.0, "{} cannot be negative!", name);
+    if discard < 1.0 {
+        Ok((discard * num_clips as f64) as usize)
+    } else {
+        ensure!(discard.fract() == 0.0, "{} must either be a whole number of clips, or a fraction below 1!", name);
+        Ok(discard as usize)
+    }
+}
+
+// Parses reducers that share a single `discard`, which is only allowed if at least one of them is "trimmed".
+// `samples` is the number of values per pixel that are discarded from.
+fn parse_reducers<const N: usize>(
+    reducers: [(Option<&[u8]>, Reducer); N],
+    discard: Option<f64>,
+    samples: usize,
+) -> Result<[Reducer; N], Error> {
+    let count = discard.map_or(Ok(1), |d| discard_count(d, samples, "discard"))?;
+
+    let mut parsed = [Reducer::Mean; N];
+    for (parsed, (reducer, default)) in parsed.iter_mut().zip(reducers) {
+        *parsed = match reducer {
+            None => default,
+            Some(b"mean") => Reducer::Mean,
+            Some(b"median") => Reducer::Median,
+            Some(b"trimmed") => Reducer::Trimmed(count),
+            Some(_) => bail!("Unknown reducer! (Only \"mean\", \"median\" and \"trimmed\" supported)"),
+        };
+    }
+
+    if parsed.iter().any(|r| matches!(r, Reducer::Trimmed(_))) {
+        ensure!(count * 2 < samples, "Cannot discard all of the input values!");
+    } else {
+        ensure!(discard.is_none(), "discard can only be used with the \"trimmed\" reducer!");
+    }
+
+    Ok(parsed)
+}
+
+// parses the scene change flag, and the frame difference threshold used where the scene change props are missing
+fn scene_change(scenechange: Option<i64>, threshold: Option<f64>) -> Result<(bool, f64), Error> {
+    let scene_change = match scenechange {
+        None | Some(1) => true,
+        Some(0) => false,
+        Some(_) => bail!("scenechange must be either 0 or 1!"),
+    };
+    let threshold = threshold.unwrap_or(0.1);
+    ensure!(threshold >= 0.0, "threshold cannot be negative!");
+    Ok((scene_change, threshold))
+}
+
+// shared argument handling of the temporal filters
+fn temporal<'core>(
+    clips: Vec<Node<'core>>,
+    radius: Option<i64>,
+    weights: Option<ValueIter<'_, 'core, f64>>,
+    boundary: Option<&[u8]>,
+    scenechange: Option<i64>,
+    threshold: Option<f64>,
+    reducer: Reducer,
+) -> Result<Temporal<'core>, Error> {
+    check_clips(&clips)?;
+    ensure!(matches!(clips[0].info().num_frames, Constant(_)), "Clips of unknown length are not supported");
+
+    let radius = radius.unwrap_or(1);
+    ensure!(radius >= 0, "radius cannot be negative!");
+    let radius = radius as usize;
+
+    let weights = match weights {
+        Some(weights) => {
+            let weights = weights.collect::<Vec<_>>();
+            ensure!(weights.len() == 2 * radius + 1, "weights must contain exactly one value per frame of the window (2 * radius + 1)!");
+            ensure!(weights.iter().all(|&w| w >= 0.0), "weights cannot be negative!");
+            // the current frame is the only one that is always part of the window
+            ensure!(weights[radius] > 0.0, "The weight of the current frame must be greater than zero!");
+            Some(weights)
+        },
+        None => None,
+    };
+
+    let boundary = match boundary {
+        None | Some(b"mirror") => Boundary::Mirror,
+        Some(b"clamp") => Boundary::Clamp,
+        Some(b"shrink") => Boundary::Shrink,
+        Some(_) => bail!("Unknown boundary! (Only \"mirror\", \"clamp\" and \"shrink\" supported)"),
+    };
+
+    let (scene_change, threshold) = scene_change(scenechange, threshold)?;
+
+    Ok(Temporal { clips, radius, weights, boundary, reducer, scene_change, threshold })
+}
+
 #[macro_export]
 macro_rules! property {
     ($prop:expr) => {
@@ -55,58 +245,577 @@ macro_rules! property {
 make_filter_function! {
     MedianFunction, "Median"
 
+    #[allow(clippy::too_many_arguments)]
     fn create_median<'core>(
         _api: API,
         _core: CoreRef<'core>,
         clips: ValueIter<'_, 'core, Node<'core>>,
+        mode: Option<&[u8]>,
+        weights: Option<ValueIter<'_, 'core, f64>>,
+        preset: Option<ValueIter<'_, 'core, i64>>,
+        ipratio: Option<ValueIter<'_, 'core, f64>>,
+        pbratio: Option<ValueIter<'_, 'core, f64>>,
+        vector: Option<i64>,
+        joint: Option<i64>,
     ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
         let clips = clips.collect::<Vec<_>>();
         check_clips(&clips)?;
 
-        Ok(Some(Box::new(Median { clips })))
+        let clip_weights = clip_weights(weights, clips.len())?;
+        let weights = frame_type_weights(preset, ipratio, pbratio, clips.len())?;
+
+        let vector = match vector {
+            None | Some(0) => false,
+            Some(1) => true,
+            Some(_) => bail!("vector must be either 0 or 1!"),
+        };
+        let format = property!(clips[0].info().format);
+        ensure!(
+            !vector || (format.sub_sampling_w() == 0 && format.sub_sampling_h() == 0),
+            "vector can only be used with formats without chroma subsampling!"
+        );
+        ensure!(!vector || mode.is_none(), "mode cannot be used together with vector!");
+
+        let joint = joint_flag(joint, format)?;
+        ensure!(!joint || !vector, "joint cannot be used together with vector!");
+        ensure!(!joint || (weights.is_none() && clip_weights.is_none()), "joint cannot be used together with weighting!");
+
+        let mode = match mode {
+            None | Some(b"mean") => MedianMode::Mean,
+            Some(b"low") => MedianMode::Low,
+            Some(b"high") => MedianMode::High,
+            Some(_) => bail!("Unknown mode! (Only \"mean\", \"low\" and \"high\" supported)"),
+        };
+
+        Ok(Some(Box::new(Median { clips, mode, weights, clip_weights, vector, joint })))
+    }
+}
+
+make_filter_function! {
+    PercentileFunction, "Percentile"
+
+    fn create_percentile<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        p: f64,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        ensure!((0.0..=100.0).contains(&p), "p must be between 0 and 100!");
+        // nearest rank
+        let rank = (p / 100.0 * (clips.len() - 1) as f64).round() as usize;
+
+        Ok(Some(Box::new(Percentile { clips, rank })))
     }
 }
 
 make_filter_function! {
     MeanFunction, "Mean"
 
+    #[allow(clippy::too_many_arguments)]
     fn create_mean<'core>(
         _api: API,
         _core: CoreRef<'core>,
         clips: ValueIter<'_, 'core, Node<'core>>,
-        preset: Option<i64>,
-        discard: Option<i64>,
+        preset: Option<ValueIter<'_, 'core, i64>>,
+        discard: Option<f64>,
+        weights: Option<ValueIter<'_, 'core, f64>>,
+        ipratio: Option<ValueIter<'_, 'core, f64>>,
+        pbratio: Option<ValueIter<'_, 'core, f64>>,
+        discard_low: Option<f64>,
+        discard_high: Option<f64>,
+        trim_mode: Option<&[u8]>,
+        mode: Option<&[u8]>,
+        p: Option<f64>,
+        robust: Option<&[u8]>,
+        scale: Option<f64>,
+        iterations: Option<i64>,
+        joint: Option<i64>,
     ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
         let clips = clips.collect::<Vec<_>>();
         check_clips(&clips)?;
 
         let input_depth = property!(clips[0].info().format).bits_per_sample();
-        if input_depth < 8 || input_depth > 32 {
+        if !(8..=32).contains(&input_depth) {
             bail!("Input depth can only be between 8 and 32");
         }
 
-        // discard + weights handling
-        // this is really horrid, there must be a more elegant way of doing this
-        let (discard, weights) = match (discard, preset) {
-
-            // discard exists, and is within bounds + preset unspecified or 0
-            (Some(d), Some(0)) | (Some(d), None) if d > 0 && d < ((clips.len() / 2) as i64) => (Some(d as usize), None),
-
-            // discard unspecified or 0 + legal preset
-            (None, Some(0)) | (Some(0), Some(0)) | (None, None) | (Some(0), None) => (None, None), // balanced ([1, 1, 1] internally])
-            (None, Some(1)) | (Some(0), Some(1)) => (None, Some([1.82, 1.30, 1.00])), // x264/5 defaults    (IP = 1.4, PB = 1.3)
-            (None, Some(2)) | (Some(0), Some(2)) => (None, Some([1.21, 1.10, 1.00])), // x264 `--tune grain` (IP = 1.1, PB = 1.1)
-            (None, Some(3)) | (Some(0), Some(3)) => (None, Some([1.10, 1.00, 1.00])), // x265 `--tune grain` (IP = 1.1, PB = 1.0)
-
-            // discard OOB
-            (Some(_), Some(0)) | (Some(_), None) => bail!("discard cannot be negative, or larger than half the length of input clip list!"),
-            // preset OOB
-            (None, Some(_)) => bail!("Unknown preset! (Only 0..3 supported, see docs for more information)"),
-            // discard & preset both specified. Should ideally be implemented, but not expected for v1.0.0
-            (Some(_), Some(_)) => bail!("preset and discard cannot be used simultaneously!"),
+        let clip_weights = clip_weights(weights, clips.len())?;
+        let weights = frame_type_weights(preset, ipratio, pbratio, clips.len())?;
+
+        let (low, high) = match (discard, discard_low, discard_high) {
+            (Some(d), None, None) => {
+                let d = discard_count(d, clips.len(), "discard")?;
+                (d, d)
+            },
+            (None, low, high) => (
+                low.map_or(Ok(0), |d| discard_count(d, clips.len(), "discard_low"))?,
+                high.map_or(Ok(0), |d| discard_count(d, clips.len(), "discard_high"))?,
+            ),
+            (Some(_), _, _) => bail!("discard cannot be used together with discard_low or discard_high!"),
+        };
+        ensure!(low + high < clips.len(), "Cannot discard all of the input clips!");
+        let winsorize = match trim_mode {
+            None | Some(b"trim") => false,
+            Some(b"winsorize") => true,
+            Some(_) => bail!("Unknown trim_mode! (Only \"trim\" and \"winsorize\" supported)"),
+        };
+        let discard = if low > 0 || high > 0 { Some(Discard { low, high, winsorize }) } else { None };
+        let kind = match (mode, p) {
+            (None, None) | (Some(b"arithmetic"), None) => MeanKind::Arithmetic,
+            (Some(b"geometric"), None) => MeanKind::Geometric,
+            (Some(b"harmonic"), None) => MeanKind::Harmonic,
+            // use the simpler special cases where possible
+            (Some(b"power"), Some(0.0)) => MeanKind::Geometric,
+            (Some(b"power"), Some(1.0)) => MeanKind::Arithmetic,
+            (Some(b"power"), Some(-1.0)) => MeanKind::Harmonic,
+            (Some(b"power"), Some(p)) if p.is_finite() => MeanKind::Power(p),
+            (Some(b"lehmer"), Some(0.0)) => MeanKind::Harmonic,
+            (Some(b"lehmer"), Some(1.0)) => MeanKind::Arithmetic,
+            (Some(b"lehmer"), Some(p)) if p.is_finite() => MeanKind::Lehmer(p),
+            (Some(b"power"), _) | (Some(b"lehmer"), _) => bail!("The power and lehmer modes require a finite p!"),
+            (None, Some(_)) | (Some(b"arithmetic"), Some(_)) | (Some(b"geometric"), Some(_)) | (Some(b"harmonic"), Some(_)) =>
+                bail!("p can only be used with the power and lehmer modes!"),
+            (Some(_), _) => bail!("Unknown mode! (Only \"arithmetic\", \"geometric\", \"harmonic\", \"power\" and \"lehmer\" supported)"),
+        };
+
+        let robust = match (robust, scale, iterations) {
+            (None, None, None) => None,
+            (Some(estimator), Some(scale), iterations) => {
+                let estimator = match estimator {
+                    b"huber" => Estimator::Huber,
+                    b"tukey" => Estimator::Tukey,
+                    _ => bail!("Unknown robust estimator! (Only \"huber\" and \"tukey\" supported)"),
+                };
+                ensure!(scale > 0.0, "scale must be greater than zero!");
+                let iterations = iterations.unwrap_or(3);
+                ensure!(iterations >= 0, "iterations cannot be negative!");
+                Some(Robust { estimator, scale, iterations: iterations as usize })
+            },
+            (Some(_), None, _) => bail!("robust requires a scale!"),
+            (None, _, _) => bail!("scale and iterations can only be used with robust!"),
+        };
+        ensure!(robust.is_none() || discard.is_none(), "robust and discard cannot be used simultaneously!");
+        ensure!(robust.is_none() || kind == MeanKind::Arithmetic, "robust can only be used with the arithmetic mean!");
+
+        let joint = joint_flag(joint, property!(clips[0].info().format))?;
+        if joint {
+            ensure!(discard.is_some(), "joint requires discard!");
+            ensure!(!winsorize, "joint cannot be used together with trim_mode=\"winsorize\"!");
+            ensure!(kind == MeanKind::Arithmetic, "joint can only be used with the arithmetic mean!");
+        }
+
+        Ok(Some(Box::new(Mean { clips, weights, clip_weights, discard, kind, robust, joint })))
+    }
+}
+
+make_filter_function! {
+    MinFunction, "Min"
+
+    fn create_min<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        Ok(Some(Box::new(Extrema { clips, extremum: Extremum::Min })))
+    }
+}
+
+make_filter_function! {
+    MaxFunction, "Max"
+
+    fn create_max<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        Ok(Some(Box::new(Extrema { clips, extremum: Extremum::Max })))
+    }
+}
+
+make_filter_function! {
+    RangeFunction, "Range"
+
+    fn create_range<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        Ok(Some(Box::new(Extrema { clips, extremum: Extremum::Range })))
+    }
+}
+
+make_filter_function! {
+    SigmaClipFunction, "SigmaClip"
+
+    fn create_sigma_clip<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        sigma_low: Option<f64>,
+        sigma_high: Option<f64>,
+        iterations: Option<i64>,
+        center: Option<&[u8]>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        let sigma_low = sigma_low.unwrap_or(3.0);
+        let sigma_high = sigma_high.unwrap_or(3.0);
+        ensure!(sigma_low >= 0.0 && sigma_high >= 0.0, "sigma_low and sigma_high cannot be negative!");
+
+        let iterations = iterations.unwrap_or(5);
+        ensure!(iterations >= 0, "iterations cannot be negative!");
+
+        let center = match center {
+            None | Some(b"median") => Center::Median,
+            Some(b"mean") => Center::Mean,
+            Some(_) => bail!("Unknown center! (Only \"median\" and \"mean\" supported)"),
+        };
+
+        Ok(Some(Box::new(SigmaClip { clips, sigma_low, sigma_high, iterations: iterations as usize, center })))
+    }
+}
+
+make_filter_function! {
+    ConsensusFunction, "Consensus"
+
+    fn create_consensus<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        tolerance: f64,
+        preferred: Option<i64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        ensure!(tolerance >= 0.0, "tolerance cannot be negative!");
+        let preferred = preferred.unwrap_or(0);
+        ensure!(preferred >= 0 && (preferred as usize) < clips.len(), "preferred must be the index of one of the input clips!");
+
+        Ok(Some(Box::new(Consensus { clips, tolerance, preferred: preferred as usize })))
+    }
+}
+
+make_filter_function! {
+    ModeFunction, "Mode"
+
+    fn create_mode<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        tie: Option<&[u8]>,
+        preferred: Option<i64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        let format = property!(clips[0].info().format);
+        ensure!(
+            format.sample_type() == SampleType::Integer && format.bits_per_sample() <= 16,
+            "Mode only supports 8..16 bit integer formats"
+        );
+
+        let tie = match (tie, preferred) {
+            (None, None) | (Some(b"low"), None) => Tie::Low,
+            (Some(b"high"), None) => Tie::High,
+            (Some(b"preferred"), preferred) => {
+                let preferred = preferred.unwrap_or(0);
+                ensure!(preferred >= 0 && (preferred as usize) < clips.len(), "preferred must be the index of one of the input clips!");
+                Tie::Preferred(preferred as usize)
+            },
+            (None, Some(_)) | (Some(b"low"), Some(_)) | (Some(b"high"), Some(_)) =>
+                bail!("preferred can only be used with tie=\"preferred\"!"),
+            (Some(_), _) => bail!("Unknown tie! (Only \"low\", \"high\" and \"preferred\" supported)"),
+        };
+
+        Ok(Some(Box::new(Mode { clips, tie })))
+    }
+}
+
+make_filter_function! {
+    WindowMedianFunction, "WindowMedian"
+
+    fn create_window_median<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        radius: Option<i64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        let radius = radius.unwrap_or(1);
+        ensure!(radius >= 0, "radius cannot be negative!");
+
+        Ok(Some(Box::new(WindowMedian { clips, radius: radius as usize })))
+    }
+}
+
+make_filter_function! {
+    PatchMeanFunction, "PatchMean"
+
+    fn create_patch_mean<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        h: f64,
+        radius: Option<i64>,
+        reference: Option<&[u8]>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        ensure!(h > 0.0, "h must be greater than zero!");
+        let radius = radius.unwrap_or(1);
+        ensure!(radius >= 0, "radius cannot be negative!");
+        let reference = match reference {
+            None | Some(b"median") => Reference::Median,
+            Some(b"mean") => Reference::Mean,
+            Some(_) => bail!("Unknown reference! (Only \"median\" and \"mean\" supported)"),
+        };
+
+        Ok(Some(Box::new(PatchMean { clips, radius: radius as usize, h, reference })))
+    }
+}
+
+make_filter_function! {
+    FrequencySplitFunction, "FrequencySplit"
+
+    fn create_frequency_split<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        radius: Option<i64>,
+        low: Option<&[u8]>,
+        high: Option<&[u8]>,
+        discard: Option<f64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        let radius = radius.unwrap_or(2);
+        ensure!(radius >= 0, "radius cannot be negative!");
+
+        let [low, high] = parse_reducers([(low, Reducer::Mean), (high, Reducer::Median)], discard, clips.len())?;
+
+        Ok(Some(Box::new(FrequencySplit { clips, radius: radius as usize, low, high })))
+    }
+}
+
+make_filter_function! {
+    BlockDCTFunction, "BlockDCT"
+
+    fn create_block_dct<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        reducer: Option<&[u8]>,
+        discard: Option<f64>,
+        overlap: Option<i64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        let [reducer] = parse_reducers([(reducer, Reducer::Median)], discard, clips.len())?;
+
+        let overlap = overlap.unwrap_or(4);
+        ensure!((0..BLOCK as i64).contains(&overlap), "overlap must be between 0 and {}!", BLOCK - 1);
+
+        Ok(Some(Box::new(BlockDct::new(clips, reducer, overlap as usize))))
+    }
+}
+
+make_filter_function! {
+    TemporalMeanFunction, "TemporalMean"
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_temporal_mean<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clip: Node<'core>,
+        radius: Option<i64>,
+        weights: Option<ValueIter<'_, 'core, f64>>,
+        boundary: Option<&[u8]>,
+        scenechange: Option<i64>,
+        threshold: Option<f64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        Ok(Some(Box::new(temporal(vec![clip], radius, weights, boundary, scenechange, threshold, Reducer::Mean)?)))
+    }
+}
+
+make_filter_function! {
+    TemporalMedianFunction, "TemporalMedian"
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_temporal_median<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clip: Node<'core>,
+        radius: Option<i64>,
+        weights: Option<ValueIter<'_, 'core, f64>>,
+        boundary: Option<&[u8]>,
+        scenechange: Option<i64>,
+        threshold: Option<f64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        Ok(Some(Box::new(temporal(vec![clip], radius, weights, boundary, scenechange, threshold, Reducer::Median)?)))
+    }
+}
+
+make_filter_function! {
+    PoolFunction, "Pool"
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_pool<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        radius: Option<i64>,
+        reducer: Option<&[u8]>,
+        discard: Option<f64>,
+        weights: Option<ValueIter<'_, 'core, f64>>,
+        boundary: Option<&[u8]>,
+        scenechange: Option<i64>,
+        threshold: Option<f64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        let mut pool = temporal(clips, radius, weights, boundary, scenechange, threshold, Reducer::Mean)?;
+
+        // number of values per pixel with the full window
+        let samples = pool.clips.len() * (2 * pool.radius + 1);
+        [pool.reducer] = parse_reducers([(reducer, Reducer::Mean)], discard, samples)?;
+
+        Ok(Some(Box::new(pool)))
+    }
+}
+
+make_filter_function! {
+    StackFunction, "Stack"
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_stack<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clip: Node<'core>,
+        factor: i64,
+        reducer: Option<&[u8]>,
+        discard: Option<f64>,
+        weights: Option<ValueIter<'_, 'core, f64>>,
+        shutter: Option<&[u8]>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        check_clips(std::slice::from_ref(&clip))?;
+        ensure!(matches!(clip.info().num_frames, Constant(_)), "Clips of unknown length are not supported");
+
+        ensure!(factor >= 1, "factor must be at least 1!");
+        let factor = factor as usize;
+
+        let [reducer] = parse_reducers([(reducer, Reducer::Mean)], discard, factor)?;
+
+        let shutter = match shutter {
+            None | Some(b"box") => Shutter::Box,
+            Some(b"triangle") => Shutter::Triangle,
+            Some(b"cosine") => Shutter::Cosine,
+            Some(_) => bail!("Unknown shutter! (Only \"box\", \"triangle\" and \"cosine\" supported)"),
+        };
+        let weights = match weights {
+            Some(weights) => {
+                ensure!(matches!(shutter, Shutter::Box), "weights and shutter cannot be used together!");
+                let weights = weights.collect::<Vec<_>>();
+                ensure!(weights.len() == factor, "weights must contain exactly one value per frame of a group (factor)!");
+                ensure!(weights.iter().all(|&w| w >= 0.0), "weights cannot be negative!");
+                // the last group can be as short as a single frame
+                ensure!(weights[0] > 0.0, "The weight of the first frame of a group must be greater than zero!");
+                Some(weights)
+            },
+            None => shutter.weights(factor),
+        };
+
+        Ok(Some(Box::new(Stack { clip, factor, weights, reducer })))
+    }
+}
+
+make_filter_function! {
+    AccumulateFunction, "Accumulate"
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_accumulate<'core>(
+        _api: API,
+        _core: CoreRef<'core>,
+        clip: Node<'core>,
+        mode: Option<&[u8]>,
+        alpha: Option<f64>,
+        depth: Option<i64>,
+        scenechange: Option<i64>,
+        threshold: Option<f64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        check_clips(std::slice::from_ref(&clip))?;
+        ensure!(matches!(clip.info().num_frames, Constant(_)), "Clips of unknown length are not supported");
+
+        let mode = match mode {
+            None | Some(b"ema") => {
+                let alpha = alpha.unwrap_or(0.1);
+                ensure!(alpha > 0.0 && alpha <= 1.0, "alpha must be greater than 0, and at most 1!");
+                Accumulation::Ema(alpha)
+            },
+            Some(b"cumulative") => {
+                ensure!(alpha.is_none(), "alpha can only be used with the \"ema\" mode!");
+                Accumulation::Cumulative
+            },
+            Some(_) => bail!("Unknown mode! (Only \"ema\" and \"cumulative\" supported)"),
+        };
+
+        let depth = match (depth, mode) {
+            (Some(depth), _) => {
+                ensure!(depth >= 0, "depth cannot be negative!");
+                Some(depth as usize)
+            },
+            // deep enough that the frames past it have less than 0.1% of the weight combined
+            (None, Accumulation::Ema(alpha)) if alpha < 1.0 => Some((0.001f64.ln() / (1.0 - alpha).ln()).ceil() as usize),
+            (None, Accumulation::Ema(_)) => Some(0),
+            // every frame since the first one, or the last scene change
+            (None, Accumulation::Cumulative) => None,
+        };
+
+        let (scene_change, threshold) = scene_change(scenechange, threshold)?;
+
+        Ok(Some(Box::new(Accumulate { clip, mode, depth, scene_change, threshold })))
+    }
+}
+
+make_filter_function! {
+    StatsFunction, "Stats"
+
+    fn create_stats<'core>(
+        _api: API,
+        core: CoreRef<'core>,
+        clips: ValueIter<'_, 'core, Node<'core>>,
+        float: Option<i64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let clips = clips.collect::<Vec<_>>();
+        check_clips(&clips)?;
+
+        let format = property!(clips[0].info().format);
+        let spread_format = match float {
+            None | Some(1) => core
+                .register_format(format.color_family(), SampleType::Float, 32, format.sub_sampling_w(), format.sub_sampling_h())
+                .ok_or_else(|| format_err!("Could not register a float format for the standard deviation and range outputs"))?,
+            Some(0) => format,
+            Some(_) => bail!("float must be either 0 or 1!"),
         };
 
-        Ok(Some(Box::new(Mean { clips, weights, discard })))
+        Ok(Some(Box::new(Stats::new(clips, spread_format))))
     }
 }
 
@@ -120,5 +829,22 @@ export_vapoursynth_plugin! {
     [
         MeanFunction::new(),
         MedianFunction::new(),
+        PercentileFunction::new(),
+        SigmaClipFunction::new(),
+        MinFunction::new(),
+        MaxFunction::new(),
+        RangeFunction::new(),
+        StatsFunction::new(),
+        ConsensusFunction::new(),
+        ModeFunction::new(),
+        WindowMedianFunction::new(),
+        PatchMeanFunction::new(),
+        FrequencySplitFunction::new(),
+        BlockDCTFunction::new(),
+        TemporalMeanFunction::new(),
+        TemporalMedianFunction::new(),
+        PoolFunction::new(),
+        StackFunction::new(),
+        AccumulateFunction::new(),
     ]
 }
@@ -3,6 +3,7 @@
 use failure::{bail, format_err, Error};
 use half::f16;
 use vapoursynth::core::CoreRef;
+use vapoursynth::format::Format;
 use vapoursynth::plugins::{Filter, FrameContext};
 use vapoursynth::prelude::*;
 use vapoursynth::video_info::VideoInfo;
@@ -68,7 +69,11 @@ macro_rules! mean_int {
 macro_rules! mean_int_discard {
     ($($fname:ident($depth:ty, $internal:ty);)*) => {
         $(
-            pub fn $fname(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], discard: usize) {
+            pub fn $fname(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], discard: Discard) {
+                let survivors = src_frames.len() - discard.low - discard.high;
+                // winsorizing keeps the discarded values (clamped), so they still count towards the mean
+                let count = if discard.winsorize { src_frames.len() } else { survivors };
+
                 // See note on reusing vecs.
                 let mut src_rows = Vec::with_capacity(src_frames.len());
                 let mut values = Vec::with_capacity(src_frames.len());
@@ -87,9 +92,15 @@ macro_rules! mean_int_discard {
                             values.extend(src_rows
                                 .iter()
                                 .map(|f| f[i] as $internal));
-                            unsafe { ultra_pepega(&mut values, discard); }
-                            let sum: $internal = values.drain(0..src_frames.len() - discard*2).sum();
-                            unsafe { std::ptr::write(pixel, (sum / (src_frames.len() - discard * 2) as $internal) as $depth) }
+                            unsafe { ultra_pepega(&mut values, discard.low, discard.high); }
+                            let sum: $internal = if discard.winsorize {
+                                let min = *values[..survivors].iter().min().unwrap();
+                                let max = *values[..survivors].iter().max().unwrap();
+                                values.iter().map(|v| (*v).clamp(min, max)).sum()
+                            } else {
+                                values[..survivors].iter().sum()
+                            };
+                            unsafe { std::ptr::write(pixel, (sum / count as $internal) as $depth) }
                             // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
                             unsafe { values.set_len(0); }
                         }
@@ -102,50 +113,202 @@ macro_rules! mean_int_discard {
     };
 }
 
+// kind of mean to calculate
+#[derive(Clone, Copy, PartialEq)]
+pub enum MeanKind {
+    Arithmetic,
+    Geometric,
+    Harmonic,
+    // power mean with exponent p
+    Power(f64),
+    // Lehmer mean with exponent p
+    Lehmer(f64),
+}
+
+impl MeanKind {
+    // Everything but the arithmetic mean is only defined for non-negative values.
+    // Float chroma is centered around 0, so it gets shifted to be non-negative for the calculation (and back afterwards)
+    fn offset(self, format: Format, plane: usize) -> f64 {
+        match self {
+            MeanKind::Arithmetic => 0.0,
+            _ if format.sample_type() == SampleType::Float && format.color_family() == ColorFamily::YUV && plane > 0 => 0.5,
+            _ => 0.0,
+        }
+    }
+
+    // Weighted mean of (value, weight) pairs, whose weights must not sum up to zero.
+    // For every mean but the arithmetic one, values below zero are treated as zero. For means that tend to zero
+    // as any value does (e.g. geometric), a zero value results in zero.
+    pub fn mean(self, values: &[(f64, f64)]) -> f64 {
+        // the arithmetic mean is fine with negative values, e.g. float chroma
+        let non_negative = self != MeanKind::Arithmetic;
+        let values = values.iter().filter(|(_, w)| *w > 0.0).map(|&(p, w)| (if non_negative { p.max(0.0) } else { p }, w));
+        let weight_sum: f64 = values.clone().map(|(_, w)| w).sum();
+
+        let absorbs_zero = match self {
+            MeanKind::Arithmetic => false,
+            MeanKind::Geometric | MeanKind::Harmonic => true,
+            MeanKind::Power(e) => e < 0.0,
+            MeanKind::Lehmer(e) => e < 1.0,
+        };
+        if absorbs_zero && values.clone().any(|(p, _)| p == 0.0) {
+            return 0.0;
+        }
+
+        match self {
+            MeanKind::Arithmetic => values.map(|(p, w)| p * w).sum::<f64>() / weight_sum,
+            MeanKind::Geometric => (values.map(|(p, w)| p.ln() * w).sum::<f64>() / weight_sum).exp(),
+            MeanKind::Harmonic => weight_sum / values.map(|(p, w)| w / p).sum::<f64>(),
+            MeanKind::Power(e) => (values.map(|(p, w)| p.powf(e) * w).sum::<f64>() / weight_sum).powf(1.0 / e),
+            MeanKind::Lehmer(e) => {
+                let denominator: f64 = values.clone().map(|(p, w)| p.powf(e - 1.0) * w).sum();
+                // only happens when every value is zero
+                if denominator == 0.0 { 0.0 } else { values.map(|(p, w)| p.powf(e) * w).sum::<f64>() / denominator }
+            },
+        }
+    }
+}
+
+// number of values to discard per pixel, from the low and high end
+#[derive(Clone, Copy)]
+pub struct Discard {
+    pub low: usize,
+    pub high: usize,
+    // clamp the discarded values to the nearest remaining value, rather than dropping them
+    pub winsorize: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum Estimator {
+    Huber,
+    Tukey,
+}
+
+// M-estimator used to softly down-weight values far away from the median, rather than discarding them
+#[derive(Clone, Copy)]
+pub struct Robust {
+    pub estimator: Estimator,
+    // distance from the centre (in sample values) at which values start getting down-weighted (Huber),
+    // or get rejected entirely (Tukey)
+    pub scale: f64,
+    pub iterations: usize,
+}
+
+impl Robust {
+    // weight of a value, given its distance from the centre in units of `scale`
+    #[inline]
+    fn weight(self, distance: f64) -> f64 {
+        let distance = distance.abs();
+        match self.estimator {
+            Estimator::Huber => if distance <= 1.0 { 1.0 } else { 1.0 / distance },
+            Estimator::Tukey => if distance < 1.0 { (1.0 - distance * distance).powi(2) } else { 0.0 },
+        }
+    }
+}
+
 pub struct Mean<'core> {
     // vector of our input clips
     pub clips: Vec<Node<'core>>,
-    // IPB muiltiplier ratios
-    pub weights: Option<[f64; 3]>,
-    pub discard: Option<usize>,
+    // per clip IPB muiltiplier ratios
+    pub weights: Option<Vec<[f64; 3]>>,
+    // static per clip weights
+    pub clip_weights: Option<Vec<f64>>,
+    pub discard: Option<Discard>,
+    pub kind: MeanKind,
+    pub robust: Option<Robust>,
+    // decide which values to discard on luma, and use the same sources for chroma
+    pub joint: bool,
 }
 
 impl<'core> Mean<'core> {
-    pub fn weighted_mean<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: [f64; 3]) {
-        let weights: Vec<_> = src_frames
-            .iter()
-            .map(|f| f.props().get::<&'_ [u8]>("_PictType").unwrap_or(b"U")[0])
-            .map(|p| match p {
-                b'I' | b'i' => weights[0],
-                b'P' | b'p' => weights[1],
-                b'B' => weights[2],
-                _ => 1.0,
-            })
-            .collect();
+    fn frame_weights(&self, src_frames: &[FrameRef]) -> Option<Vec<f64>> {
+        frame_weights(src_frames, self.weights.as_deref(), self.clip_weights.as_deref())
+    }
 
+    pub fn weighted_mean<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: &[f64], kind: MeanKind) {
         // we do the division once outside of the loop so we only need multiplication in the inner loop
         let reciprocal = 1.0 / weights.iter().sum::<f64>();
 
         // See note on reusing vecs.
         let mut src_rows = Vec::with_capacity(src_frames.len());
+        let mut values = Vec::with_capacity(src_frames.len());
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+
+        for plane in 0..format.plane_count() {
+            let offset = kind.offset(format, plane);
+            for row in 0..out_frame.height(plane) {
+                // Vec reuse: filling
+                src_rows.extend(src_frames
+                    .iter()
+                    .map(|f| f.plane_row::<T>(plane, row)));
+                for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                    let mean = if kind == MeanKind::Arithmetic {
+                        let weighted_sum: f64 = src_rows
+                            .iter()
+                            .map(|f| f[i].to_f64())
+                            .zip(weights.iter())
+                            .map(|(p, w)| p * w)
+                            .sum();
+                        weighted_sum * reciprocal
+                    } else {
+                        // Vec reuse: filling
+                        values.extend(src_rows
+                            .iter()
+                            .map(|f| f[i].to_f64() + offset)
+                            .zip(weights.iter().copied()));
+                        let mean = kind.mean(&values) - offset;
+                        // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+                        unsafe { values.set_len(0); }
+                        mean
+                    };
+                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(mean)) }
+                }
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+                unsafe { src_rows.set_len(0); }
+            }
+        }
+    }
+
+    pub fn weighted_mean_discard<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: &[f64], discard: Discard, kind: MeanKind) {
+        let survivors = src_frames.len() - discard.low - discard.high;
+
+        // See note on reusing vecs.
+        let mut src_rows = Vec::with_capacity(src_frames.len());
+        let mut values = Vec::with_capacity(src_frames.len());
 
         // `out_frame` has the same format as the input clips
         let format = out_frame.format();
 
         for plane in 0..format.plane_count() {
+            let offset = kind.offset(format, plane);
             for row in 0..out_frame.height(plane) {
                 // Vec reuse: filling
                 src_rows.extend(src_frames
                     .iter()
                     .map(|f| f.plane_row::<T>(plane, row)));
                 for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
-                    let weighted_sum: f64 = src_rows
+                    // Vec reuse: filling
+                    // (value, weight) pairs, so that each weight follows its value through the selection
+                    values.extend(src_rows
                         .iter()
-                        .map(|f| f[i].to_f64())
-                        .zip(weights.iter())
-                        .map(|(p, w)| p * w)
-                        .sum();
-                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(weighted_sum * reciprocal)) }
+                        .map(|f| f[i].to_f64() + offset)
+                        .zip(weights.iter().copied()));
+                    unsafe { ultra_pepega(&mut values, discard.low, discard.high); }
+                    if discard.winsorize {
+                        let (min, max) = min_max(values[..survivors].iter().map(|(p, _)| *p));
+                        values.iter_mut().for_each(|(p, _)| *p = p.max(min).min(max));
+                    }
+                    let kept = if discard.winsorize { &mut values[..] } else { &mut values[..survivors] };
+                    // if only zero weighted values are left, fall back to their unweighted mean
+                    if kept.iter().all(|(_, w)| *w == 0.0) {
+                        kept.iter_mut().for_each(|(_, w)| *w = 1.0);
+                    }
+                    let mean = kind.mean(kept) - offset;
+                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(mean)) }
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+                    unsafe { values.set_len(0); }
                 }
                 // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
                 unsafe { src_rows.set_len(0); }
@@ -153,8 +316,67 @@ impl<'core> Mean<'core> {
         }
     }
 
-    pub fn mean_float_discard<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], discard: usize) {
-        let reciprocal = 1.0 / (src_frames.len() - discard * 2) as f64;
+    pub fn robust_mean<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: &[f64], robust: Robust) {
+        // See note on reusing vecs.
+        let mut src_rows = Vec::with_capacity(src_frames.len());
+        let mut values = Vec::with_capacity(src_frames.len());
+        // separate copy of the values for the median, since it needs to sort them
+        let mut sorted = Vec::with_capacity(src_frames.len());
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+
+        for plane in 0..format.plane_count() {
+            for row in 0..out_frame.height(plane) {
+                // Vec reuse: filling
+                src_rows.extend(src_frames
+                    .iter()
+                    .map(|f| f.plane_row::<T>(plane, row)));
+                for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                    // Vec reuse: filling
+                    values.extend(src_rows
+                        .iter()
+                        .map(|f| f[i].to_f64()));
+                    sorted.extend_from_slice(&values);
+
+                    // iteratively reweighted mean, starting from the median
+                    let mut center = median(&mut sorted);
+                    for _ in 0..robust.iterations {
+                        let (weighted_sum, weight_sum) = values
+                            .iter()
+                            .zip(weights.iter())
+                            .map(|(p, w)| (p, w * robust.weight((p - center) / robust.scale)))
+                            .fold((0.0, 0.0), |(s, t), (p, w)| (s + p * w, t + w));
+                        // every value got rejected, so we stick with what we've got
+                        if weight_sum == 0.0 {
+                            break;
+                        }
+                        center = weighted_sum / weight_sum;
+                    }
+
+                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(center)) }
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+                    unsafe { values.set_len(0); }
+                    unsafe { sorted.set_len(0); }
+                }
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+                unsafe { src_rows.set_len(0); }
+            }
+        }
+    }
+
+    // trimmed mean, with the decision which values to discard made on luma, and applied to chroma as well
+    pub fn mean_joint<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: Option<&[f64]>, discard: Discard) {
+        let survivors = src_frames.len() - discard.low - discard.high;
+        joint_luma_chroma::<T>(out_frame, src_frames, weights, survivors, |pairs| unsafe {
+            ultra_pepega(pairs, discard.low, discard.high);
+        });
+    }
+
+    pub fn mean_float_discard<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], discard: Discard) {
+        let survivors = src_frames.len() - discard.low - discard.high;
+        // winsorizing keeps the discarded values (clamped), so they still count towards the mean
+        let reciprocal = 1.0 / if discard.winsorize { src_frames.len() } else { survivors } as f64;
 
         // See note on reusing vecs.
         let mut src_rows = Vec::with_capacity(src_frames.len());
@@ -174,8 +396,13 @@ impl<'core> Mean<'core> {
                     values.extend(src_rows
                         .iter()
                         .map(|f| f[i].to_f64()));
-                    unsafe { ultra_pepega(&mut values, discard); }
-                    let sum: f64 = values.drain(0..src_frames.len() - discard*2).sum();
+                    unsafe { ultra_pepega(&mut values, discard.low, discard.high); }
+                    let sum: f64 = if discard.winsorize {
+                        let (min, max) = min_max(values[..survivors].iter().copied());
+                        values.iter().map(|v| v.max(min).min(max)).sum()
+                    } else {
+                        values[..survivors].iter().sum()
+                    };
                     unsafe { std::ptr::write(pixel, F64Convertible::from_f64(sum * reciprocal)) }
                     // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
                     unsafe { values.set_len(0); }
@@ -218,12 +445,18 @@ impl<'core> Mean<'core> {
         mean_u8(u8, u16);
         mean_u16(u16, u32);
         mean_u32(u32, u64);
+        // for the temporal filters, where the number of frames isn't limited to the number of clips
+        mean_u8_wide(u8, u32);
+        mean_u16_wide(u16, u64);
     }
 
     mean_int_discard! {
         mean_u8_discard(u8, u16);
         mean_u16_discard(u16, u32);
         mean_u32_discard(u32, u64);
+        // see `mean_u8_wide`
+        mean_u8_wide_discard(u8, u32);
+        mean_u16_wide_discard(u16, u64);
     }
 }
 
@@ -267,18 +500,50 @@ impl<'core> Filter<'core> for Mean<'core> {
         let prop_src = Some(&*src_frames[0]);
         let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
 
+        if let (true, Some(discard)) = (self.joint, self.discard) {
+            let weights = self.frame_weights(&src_frames);
+            match (format.sample_type(), format.bits_per_sample()) {
+                (SampleType::Integer,       8) => Self::mean_joint::<u8> (&mut out_frame, &src_frames, weights.as_deref(), discard),
+                (SampleType::Integer,  9..=16) => Self::mean_joint::<u16>(&mut out_frame, &src_frames, weights.as_deref(), discard),
+                (SampleType::Integer, 17..=32) => Self::mean_joint::<u32>(&mut out_frame, &src_frames, weights.as_deref(), discard),
+                (SampleType::Float,        16) => Self::mean_joint::<f16>(&mut out_frame, &src_frames, weights.as_deref(), discard),
+                (SampleType::Float,        32) => Self::mean_joint::<f32>(&mut out_frame, &src_frames, weights.as_deref(), discard),
+                (sample_type, bits_per_sample) =>
+                    bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+            }
+
+            return Ok(out_frame.into());
+        }
+
+        // the other kinds of means, and the robust mean are only implemented by the weighted kernels
+        let weights = match (self.kind, self.robust) {
+            (MeanKind::Arithmetic, None) => self.frame_weights(&src_frames),
+            _ => self.frame_weights(&src_frames).or_else(|| Some(vec![1.0; src_frames.len()])),
+        };
+
         // match input sample type and bits per sample
-        match (self.weights, self.discard) {
-            (Some(weights), None) => match (format.sample_type(), format.bits_per_sample()) {
-                (SampleType::Integer,       8) => Self::weighted_mean::<u8> (&mut out_frame, &src_frames, weights),
-                (SampleType::Integer,  9..=16) => Self::weighted_mean::<u16>(&mut out_frame, &src_frames, weights),
-                (SampleType::Integer, 17..=32) => Self::weighted_mean::<u32>(&mut out_frame, &src_frames, weights),
-                (SampleType::Float,        16) => Self::weighted_mean::<f16>(&mut out_frame, &src_frames, weights),
-                (SampleType::Float,        32) => Self::weighted_mean::<f32>(&mut out_frame, &src_frames, weights),
+        match (weights, self.discard, self.robust) {
+            (Some(weights), None, Some(robust)) => match (format.sample_type(), format.bits_per_sample()) {
+                (SampleType::Integer,       8) => Self::robust_mean::<u8> (&mut out_frame, &src_frames, &weights, robust),
+                (SampleType::Integer,  9..=16) => Self::robust_mean::<u16>(&mut out_frame, &src_frames, &weights, robust),
+                (SampleType::Integer, 17..=32) => Self::robust_mean::<u32>(&mut out_frame, &src_frames, &weights, robust),
+                (SampleType::Float,        16) => Self::robust_mean::<f16>(&mut out_frame, &src_frames, &weights, robust),
+                (SampleType::Float,        32) => Self::robust_mean::<f32>(&mut out_frame, &src_frames, &weights, robust),
                 (sample_type, bits_per_sample) =>
                     bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
             },
-            (None, Some(discard)) => match (format.sample_type(), format.bits_per_sample()) {
+            (_, _, Some(_)) =>
+                bail!("Tried to use the robust mean with discard. This shouldn't be possible."),
+            (Some(weights), None, None) => match (format.sample_type(), format.bits_per_sample()) {
+                (SampleType::Integer,       8) => Self::weighted_mean::<u8> (&mut out_frame, &src_frames, &weights, self.kind),
+                (SampleType::Integer,  9..=16) => Self::weighted_mean::<u16>(&mut out_frame, &src_frames, &weights, self.kind),
+                (SampleType::Integer, 17..=32) => Self::weighted_mean::<u32>(&mut out_frame, &src_frames, &weights, self.kind),
+                (SampleType::Float,        16) => Self::weighted_mean::<f16>(&mut out_frame, &src_frames, &weights, self.kind),
+                (SampleType::Float,        32) => Self::weighted_mean::<f32>(&mut out_frame, &src_frames, &weights, self.kind),
+                (sample_type, bits_per_sample) =>
+                    bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+            },
+            (None, Some(discard), None) => match (format.sample_type(), format.bits_per_sample()) {
                 (SampleType::Integer,       8) => Self::mean_u8_discard(&mut out_frame, &src_frames, discard),
                 (SampleType::Integer,  9..=16) => Self::mean_u16_discard(&mut out_frame, &src_frames, discard),
                 (SampleType::Integer, 17..=32) => Self::mean_u32_discard(&mut out_frame, &src_frames, discard),
@@ -287,7 +552,7 @@ impl<'core> Filter<'core> for Mean<'core> {
                 (sample_type, bits_per_sample) =>
                     bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
             },
-            (None, None) => match (format.sample_type(), format.bits_per_sample()) {
+            (None, None, None) => match (format.sample_type(), format.bits_per_sample()) {
                 (SampleType::Integer,       8) => Self::mean_u8 (&mut out_frame, &src_frames),
                 (SampleType::Integer,  9..=16) => Self::mean_u16(&mut out_frame, &src_frames),
                 (SampleType::Integer, 17..=32) => Self::mean_u32(&mut out_frame, &src_frames),
@@ -296,11 +561,29 @@ impl<'core> Filter<'core> for Mean<'core> {
                 (sample_type, bits_per_sample) =>
                     bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
             },
-            (Some(_), Some(_)) =>
-                bail!("Tried to use weighting and discard. This shouldn't be possible."),
+            (Some(weights), Some(discard), None) => match (format.sample_type(), format.bits_per_sample()) {
+                (SampleType::Integer,       8) => Self::weighted_mean_discard::<u8> (&mut out_frame, &src_frames, &weights, discard, self.kind),
+                (SampleType::Integer,  9..=16) => Self::weighted_mean_discard::<u16>(&mut out_frame, &src_frames, &weights, discard, self.kind),
+                (SampleType::Integer, 17..=32) => Self::weighted_mean_discard::<u32>(&mut out_frame, &src_frames, &weights, discard, self.kind),
+                (SampleType::Float,        16) => Self::weighted_mean_discard::<f16>(&mut out_frame, &src_frames, &weights, discard, self.kind),
+                (SampleType::Float,        32) => Self::weighted_mean_discard::<f32>(&mut out_frame, &src_frames, &weights, discard, self.kind),
+                (sample_type, bits_per_sample) =>
+                    bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+            },
         }
 
         // return our resulting frame
         Ok(out_frame.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_mean_keeps_negative_values() {
+        let mean = MeanKind::Arithmetic.mean(&[(-0.4, 1.0), (-0.2, 1.0)]);
+        assert!((mean - -0.3).abs() < 1e-12);
+    }
+}
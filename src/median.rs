@@ -15,7 +15,7 @@ use crate::{PLUGIN_NAME, property};
 macro_rules! median_int {
     ($($fname:ident($depth:ty, $internal:ty);)*) => {
         $(
-            pub fn $fname(out_frame: &mut FrameRefMut, src_frames: &[FrameRef]) {
+            pub fn $fname(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], mode: MedianMode) {
                 let mut src_rows = Vec::with_capacity(src_frames.len());
                 let mut values = Vec::with_capacity(src_frames.len());
 
@@ -40,11 +40,16 @@ macro_rules! median_int {
                                 values[(values.len() - 1) >> 1]
                             } else {
                                 let middle = values.len() >> 1;
-                                (values[middle - 1] + values[middle]) >> 1
+                                match mode {
+                                    MedianMode::Mean => (values[middle - 1] + values[middle]) >> 1,
+                                    MedianMode::Low => values[middle - 1],
+                                    MedianMode::High => values[middle],
+                                }
                             };
 
                             unsafe { std::ptr::write(pixel, data as $depth) }
-
+                            // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+                            unsafe { values.set_len(0); }
                         }
                         // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
                         unsafe { src_rows.set_len(0); }
@@ -55,11 +60,31 @@ macro_rules! median_int {
     };
 }
 
+// what to return for an even number of clips, where there is no single middle value
+#[derive(Clone, Copy)]
+pub enum MedianMode {
+    // average of the two middle values
+    Mean,
+    // lower of the two middle values
+    Low,
+    // higher of the two middle values
+    High,
+}
+
 pub struct Median<'core> {
     pub clips: Vec<Node<'core>>,
+    pub mode: MedianMode,
+    // per clip IPB muiltiplier ratios
+    pub weights: Option<Vec<[f64; 3]>>,
+    // static per clip weights
+    pub clip_weights: Option<Vec<f64>>,
+    // pick whole pixels across all planes, rather than every plane on its own
+    pub vector: bool,
+    // decide which sources to use on luma, and use the same sources for chroma
+    pub joint: bool,
 }
 impl<'core> Median<'core> {
-    pub fn median_float<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef]) {
+    pub fn median_float<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], mode: MedianMode) {
         // See note on reusing vecs in mean.rs
         let mut src_rows = Vec::with_capacity(src_frames.len());
         let mut values = Vec::with_capacity(src_frames.len());
@@ -85,10 +110,16 @@ impl<'core> Median<'core> {
                         values[(values.len() - 1) >> 1]
                     } else {
                         let middle = values.len() >> 1;
-                        (values[middle - 1] + values[middle]) / 2.0
+                        match mode {
+                            MedianMode::Mean => (values[middle - 1] + values[middle]) / 2.0,
+                            MedianMode::Low => values[middle - 1],
+                            MedianMode::High => values[middle],
+                        }
                     };
 
                     unsafe { std::ptr::write(pixel, F64Convertible::from_f64(data)) }
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+                    unsafe { values.set_len(0); }
                 }
                 // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
                 unsafe { src_rows.set_len(0); }
@@ -96,6 +127,108 @@ impl<'core> Median<'core> {
         }
     }
 
+    pub fn weighted_median<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: &[f64], mode: MedianMode) {
+        // (value, weight) pairs, so that each weight follows its value through the sorting
+        let mut weighted = Vec::with_capacity(src_frames.len());
+        let half = weights.iter().sum::<f64>() / 2.0;
+
+        reduce_pixels::<T>(out_frame, src_frames, |values| {
+            // zero weighted values can never be the median
+            weighted.extend(values.iter().copied().zip(weights.iter().copied()).filter(|&(_, w)| w > 0.0));
+            weighted.sort_unstable_by(|a: &(f64, f64), b| a.0.partial_cmp(&b.0).unwrap());
+
+            // the median is the first value where the cumulative weight reaches half of the total weight
+            let mut cumulative = 0.0;
+            let mut index = 0;
+            while cumulative + weighted[index].1 < half && index + 1 < weighted.len() {
+                cumulative += weighted[index].1;
+                index += 1;
+            }
+
+            // if it's reached exactly, the next value is a median as well, like with an even number of unweighted values
+            let data = if cumulative + weighted[index].1 == half && index + 1 < weighted.len() {
+                match mode {
+                    MedianMode::Mean => (weighted[index].0 + weighted[index + 1].0) / 2.0,
+                    MedianMode::Low => weighted[index].0,
+                    MedianMode::High => weighted[index + 1].0,
+                }
+            } else {
+                weighted[index].0
+            };
+
+            weighted.clear();
+            data
+        });
+    }
+
+    // Picks the source whose pixel (across all planes) has the smallest (weighted) summed distance to all other sources,
+    // so the output is always a colour that one of the sources actually contains. Only works without subsampling.
+    pub fn vector_median<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: Option<&[f64]>) {
+        let clips = src_frames.len();
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+        let planes = format.plane_count();
+
+        // See note on reusing vecs in mean.rs
+        // rows of all planes, indexed by `plane * clips + clip`
+        let mut src_rows = Vec::with_capacity(planes * clips);
+        // the source picked for each pixel of the current row
+        let mut picks = Vec::with_capacity(out_frame.width(0));
+
+        for row in 0..out_frame.height(0) {
+            // Vec reuse: filling
+            src_rows.extend((0..planes)
+                .flat_map(|plane| src_frames.iter().map(move |f| f.plane_row::<T>(plane, row))));
+
+            let distance = |i: usize, a: usize, b: usize| (0..planes)
+                .map(|plane| src_rows[plane * clips + a][i].to_f64() - src_rows[plane * clips + b][i].to_f64())
+                .map(|d| d * d)
+                .sum::<f64>()
+                .sqrt();
+
+            // Vec reuse: filling
+            picks.extend((0..out_frame.width(0)).map(|i| {
+                (0..clips)
+                    .map(|a| (0..clips).map(|b| distance(i, a, b) * weights.map_or(1.0, |w| w[b])).sum::<f64>())
+                    .enumerate()
+                    .fold((0, f64::INFINITY), |best, (a, d)| if d < best.1 { (a, d) } else { best })
+                    .0
+            }));
+
+            for plane in 0..planes {
+                for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                    unsafe { std::ptr::write(pixel, src_rows[plane * clips + picks[i]][i]) }
+                }
+            }
+
+            // Vec reuse: (unsafe) clearing; see `set_len` SAFETY.
+            unsafe { src_rows.set_len(0); }
+            unsafe { picks.set_len(0); }
+        }
+    }
+
+    // median, with the decision which source(s) to use made on luma, and applied to chroma as well
+    pub fn median_joint<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], mode: MedianMode) {
+        let len = src_frames.len();
+        // first of the middle values, and how many of them to use
+        let (start, count) = if len & 1 == 1 {
+            ((len - 1) >> 1, 1)
+        } else {
+            let middle = len >> 1;
+            match mode {
+                MedianMode::Mean => (middle - 1, 2),
+                MedianMode::Low => (middle - 1, 1),
+                MedianMode::High => (middle, 1),
+            }
+        };
+
+        joint_luma_chroma::<T>(out_frame, src_frames, None, count, |pairs| {
+            pairs.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            pairs.rotate_left(start);
+        });
+    }
+
     median_int! {
         median_u8(u8, u16);
         median_u16(u16, u32);
@@ -138,12 +271,56 @@ impl<'core> Filter<'core> for Median<'core> {
         let prop_src = Some(&*src_frames[0]);
         let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
 
+        let weights = frame_weights(&src_frames, self.weights.as_deref(), self.clip_weights.as_deref());
+
+        if self.vector {
+            match (format.sample_type(), format.bits_per_sample()) {
+                (SampleType::Integer,       8) => Self::vector_median::<u8> (&mut out_frame, &src_frames, weights.as_deref()),
+                (SampleType::Integer,  9..=16) => Self::vector_median::<u16>(&mut out_frame, &src_frames, weights.as_deref()),
+                (SampleType::Integer, 17..=32) => Self::vector_median::<u32>(&mut out_frame, &src_frames, weights.as_deref()),
+                (SampleType::Float,        16) => Self::vector_median::<f16>(&mut out_frame, &src_frames, weights.as_deref()),
+                (SampleType::Float,        32) => Self::vector_median::<f32>(&mut out_frame, &src_frames, weights.as_deref()),
+                (sample_type, bits_per_sample) =>
+                    bail!("{}: input depth {} not supported for sample type {}. This shouldn't be possible", PLUGIN_NAME, bits_per_sample, sample_type),
+            }
+
+            return Ok(out_frame.into());
+        }
+
+        if self.joint {
+            match (format.sample_type(), format.bits_per_sample()) {
+                (SampleType::Integer,       8) => Self::median_joint::<u8> (&mut out_frame, &src_frames, self.mode),
+                (SampleType::Integer,  9..=16) => Self::median_joint::<u16>(&mut out_frame, &src_frames, self.mode),
+                (SampleType::Integer, 17..=32) => Self::median_joint::<u32>(&mut out_frame, &src_frames, self.mode),
+                (SampleType::Float,        16) => Self::median_joint::<f16>(&mut out_frame, &src_frames, self.mode),
+                (SampleType::Float,        32) => Self::median_joint::<f32>(&mut out_frame, &src_frames, self.mode),
+                (sample_type, bits_per_sample) =>
+                    bail!("{}: input depth {} not supported for sample type {}. This shouldn't be possible", PLUGIN_NAME, bits_per_sample, sample_type),
+            }
+
+            return Ok(out_frame.into());
+        }
+
+        if let Some(weights) = weights {
+            match (format.sample_type(), format.bits_per_sample()) {
+                (SampleType::Integer,       8) => Self::weighted_median::<u8> (&mut out_frame, &src_frames, &weights, self.mode),
+                (SampleType::Integer,  9..=16) => Self::weighted_median::<u16>(&mut out_frame, &src_frames, &weights, self.mode),
+                (SampleType::Integer, 17..=32) => Self::weighted_median::<u32>(&mut out_frame, &src_frames, &weights, self.mode),
+                (SampleType::Float,        16) => Self::weighted_median::<f16>(&mut out_frame, &src_frames, &weights, self.mode),
+                (SampleType::Float,        32) => Self::weighted_median::<f32>(&mut out_frame, &src_frames, &weights, self.mode),
+                (sample_type, bits_per_sample) =>
+                    bail!("{}: input depth {} not supported for sample type {}. This shouldn't be possible", PLUGIN_NAME, bits_per_sample, sample_type),
+            }
+
+            return Ok(out_frame.into());
+        }
+
         match (format.sample_type(), format.bits_per_sample()) {
-            (SampleType::Integer,       8) => Self::median_u8(&mut out_frame, &src_frames),
-            (SampleType::Integer,  9..=16) => Self::median_u16(&mut out_frame, &src_frames),
-            (SampleType::Integer, 17..=32) => Self::median_u32(&mut out_frame, &src_frames),
-            (SampleType::Float,        16) => Self::median_float::<f16>(&mut out_frame, &src_frames),
-            (SampleType::Float,        32) => Self::median_float::<f32>(&mut out_frame, &src_frames),
+            (SampleType::Integer,       8) => Self::median_u8(&mut out_frame, &src_frames, self.mode),
+            (SampleType::Integer,  9..=16) => Self::median_u16(&mut out_frame, &src_frames, self.mode),
+            (SampleType::Integer, 17..=32) => Self::median_u32(&mut out_frame, &src_frames, self.mode),
+            (SampleType::Float,        16) => Self::median_float::<f16>(&mut out_frame, &src_frames, self.mode),
+            (SampleType::Float,        32) => Self::median_float::<f32>(&mut out_frame, &src_frames, self.mode),
             (sample_type, bits_per_sample) =>
                 bail!("{}: input depth {} not supported for sample type {}. This shouldn't be possible", PLUGIN_NAME, bits_per_sample, sample_type),
         }
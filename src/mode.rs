@@ -0,0 +1,116 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use vapoursynth::component::Component;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::{PLUGIN_NAME, property};
+
+// which value to return when several are equally frequent
+#[derive(Clone, Copy)]
+pub enum Tie {
+    Low,
+    High,
+    // value of the given clip if it is one of them, otherwise the one of the first clip that is
+    Preferred(usize),
+}
+
+pub struct Mode<'core> {
+    pub clips: Vec<Node<'core>>,
+    pub tie: Tie,
+}
+
+impl<'core> Mode<'core> {
+    pub fn mode<T: Component + Copy + Ord + Into<usize>>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], tie: Tie) {
+        // Counting every value in a histogram avoids having to sort the values.
+        // It covers every value the sample type can hold, so out of range values can't go out of bounds.
+        let mut counts = vec![0u32; 1 << (8 * std::mem::size_of::<T>())];
+
+        // See note on reusing vecs in mean.rs
+        let mut src_rows = Vec::with_capacity(src_frames.len());
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+
+        for plane in 0..format.plane_count() {
+            for row in 0..out_frame.height(plane) {
+                // Vec reuse: filling
+                src_rows.extend(src_frames
+                    .iter()
+                    .map(|f| f.plane_row::<T>(plane, row)));
+                for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                    let mut max_count = 0;
+                    for f in &src_rows {
+                        let count = &mut counts[f[i].into()];
+                        *count += 1;
+                        max_count = max_count.max(*count);
+                    }
+
+                    let mut candidates = src_rows.iter().map(|f| f[i]).filter(|&v| counts[v.into()] == max_count);
+                    let data = match tie {
+                        Tie::Low => candidates.min().unwrap(),
+                        Tie::High => candidates.max().unwrap(),
+                        Tie::Preferred(clip) if counts[src_rows[clip][i].into()] == max_count => src_rows[clip][i],
+                        Tie::Preferred(_) => candidates.next().unwrap(),
+                    };
+
+                    unsafe { std::ptr::write(pixel, data) }
+
+                    // only reset the counts that were actually used, rather than the whole histogram
+                    for f in &src_rows {
+                        counts[f[i].into()] = 0;
+                    }
+                }
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                unsafe { src_rows.set_len(0); }
+            }
+        }
+    }
+}
+
+impl<'core> Filter<'core> for Mode<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,      8) => Self::mode::<u8> (&mut out_frame, &src_frames, self.tie),
+            (SampleType::Integer, 9..=16) => Self::mode::<u16>(&mut out_frame, &src_frames, self.tie),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}. This shouldn't be possible", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+// per pixel estimate the patches of the sources are compared against
+#[derive(Clone, Copy)]
+pub enum Reference {
+    Median,
+    Mean,
+}
+
+pub struct PatchMean<'core> {
+    pub clips: Vec<Node<'core>>,
+    pub radius: usize,
+    // filtering strength, in sample values. Larger values weight dissimilar patches more evenly
+    pub h: f64,
+    pub reference: Reference,
+}
+
+impl<'core> PatchMean<'core> {
+    // Non-local means style weighted mean, where each source's pixel is weighted by how similar the patch around it is to
+    // the same patch of the reference estimate: w = exp(-d / h²), d being the mean squared difference over the patch.
+    pub fn patch_mean<T: F64Convertible>(&self, out_frame: &mut FrameRefMut, src_frames: &[FrameRef]) {
+        let clips = src_frames.len();
+        let h2 = self.h * self.h;
+
+        // See note on reusing vecs in mean.rs
+        let mut src_rows = Vec::with_capacity(clips);
+        let mut values = Vec::with_capacity(clips);
+        let mut weighted = Vec::with_capacity(clips);
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+
+        for plane in 0..format.plane_count() {
+            let (width, height) = (out_frame.width(plane), out_frame.height(plane));
+            let size = width * height;
+
+            // reference estimate for the whole plane, since patches need its neighbouring rows as well
+            let mut reference = Vec::with_capacity(size);
+            for row in 0..height {
+                // Vec reuse: filling
+                src_rows.extend(src_frames
+                    .iter()
+                    .map(|f| f.plane_row::<T>(plane, row)));
+                for i in 0..width {
+                    // Vec reuse: filling
+                    values.extend(src_rows
+                        .iter()
+                        .map(|f| f[i].to_f64()));
+                    reference.push(match self.reference {
+                        Reference::Median => median(&mut values),
+                        Reference::Mean => values.iter().sum::<f64>() / clips as f64,
+                    });
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                    unsafe { values.set_len(0); }
+                }
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                unsafe { src_rows.set_len(0); }
+            }
+
+            // per clip patch distances: squared differences to the reference, averaged over the patch
+            let mut distances = vec![0.0; clips * size];
+            for (f, distances) in src_frames.iter().zip(distances.chunks_exact_mut(size)) {
+                for row in 0..height {
+                    let src_row = f.plane_row::<T>(plane, row);
+                    for i in 0..width {
+                        let difference = src_row[i].to_f64() - reference[row * width + i];
+                        distances[row * width + i] = difference * difference;
+                    }
+                }
+                box_blur(distances, width, height, self.radius);
+            }
+
+            for row in 0..height {
+                // Vec reuse: filling
+                src_rows.extend(src_frames
+                    .iter()
+                    .map(|f| f.plane_row::<T>(plane, row)));
+                for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                    // Vec reuse: filling
+                    weighted.extend(src_rows
+                        .iter()
+                        .enumerate()
+                        .map(|(clip, f)| (f[i].to_f64(), (-distances[clip * size + row * width + i] / h2).exp())));
+                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(weighted_average(&weighted))) }
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                    unsafe { weighted.set_len(0); }
+                }
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                unsafe { src_rows.set_len(0); }
+            }
+        }
+    }
+}
+
+impl<'core> Filter<'core> for PatchMean<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => self.patch_mean::<u8> (&mut out_frame, &src_frames),
+            (SampleType::Integer,  9..=16) => self.patch_mean::<u16>(&mut out_frame, &src_frames),
+            (SampleType::Integer, 17..=32) => self.patch_mean::<u32>(&mut out_frame, &src_frames),
+            (SampleType::Float,        16) => self.patch_mean::<f16>(&mut out_frame, &src_frames),
+            (SampleType::Float,        32) => self.patch_mean::<f32>(&mut out_frame, &src_frames),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+pub struct Percentile<'core> {
+    pub clips: Vec<Node<'core>>,
+    // index into the sorted input pixels, so that the output is always one of the inputs
+    pub rank: usize,
+}
+
+impl<'core> Percentile<'core> {
+    pub fn percentile<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], rank: usize) {
+        reduce_pixels::<T>(out_frame, src_frames, |values| {
+            // we only need the one value in place, not the whole thing sorted
+            *values.select_nth_unstable_by(rank, |a, b| a.partial_cmp(b).unwrap()).1
+        });
+    }
+}
+
+impl<'core> Filter<'core> for Percentile<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Self::percentile::<u8> (&mut out_frame, &src_frames, self.rank),
+            (SampleType::Integer,  9..=16) => Self::percentile::<u16>(&mut out_frame, &src_frames, self.rank),
+            (SampleType::Integer, 17..=32) => Self::percentile::<u32>(&mut out_frame, &src_frames, self.rank),
+            (SampleType::Float,        16) => Self::percentile::<f16>(&mut out_frame, &src_frames, self.rank),
+            (SampleType::Float,        32) => Self::percentile::<f32>(&mut out_frame, &src_frames, self.rank),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+// scales the median absolute deviation so that it estimates the standard deviation of normally distributed data
+const MAD_SCALE: f64 = 1.4826;
+
+#[derive(Clone, Copy)]
+pub enum Center {
+    // median, with the (scaled) median absolute deviation as spread
+    Median,
+    // mean, with the standard deviation as spread
+    Mean,
+}
+
+pub struct SigmaClip<'core> {
+    pub clips: Vec<Node<'core>>,
+    pub sigma_low: f64,
+    pub sigma_high: f64,
+    pub iterations: usize,
+    pub center: Center,
+}
+
+impl<'core> SigmaClip<'core> {
+    pub fn sigma_clip<T: F64Convertible>(&self, out_frame: &mut FrameRefMut, src_frames: &[FrameRef]) {
+        // scratch space for the deviations from the median
+        let mut deviations = Vec::with_capacity(src_frames.len());
+
+        reduce_pixels::<T>(out_frame, src_frames, |values| {
+            for _ in 0..self.iterations {
+                let (center, spread) = match self.center {
+                    Center::Median => {
+                        let center = median(values);
+                        deviations.extend(values.iter().map(|v| (v - center).abs()));
+                        let spread = median(&mut deviations) * MAD_SCALE;
+                        deviations.clear();
+                        (center, spread)
+                    },
+                    Center::Mean => {
+                        let center = values.iter().sum::<f64>() / values.len() as f64;
+                        let variance = values.iter().map(|v| (v - center) * (v - center)).sum::<f64>() / values.len() as f64;
+                        (center, variance.sqrt())
+                    },
+                };
+
+                let (low, high) = (center - self.sigma_low * spread, center + self.sigma_high * spread);
+                let kept = values.iter().filter(|&&v| v >= low && v <= high).count();
+                // stop once nothing else gets rejected, and never reject every value
+                if kept == values.len() || kept == 0 {
+                    break;
+                }
+                values.retain(|&v| v >= low && v <= high);
+            }
+
+            values.iter().sum::<f64>() / values.len() as f64
+        });
+    }
+}
+
+impl<'core> Filter<'core> for SigmaClip<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => self.sigma_clip::<u8> (&mut out_frame, &src_frames),
+            (SampleType::Integer,  9..=16) => self.sigma_clip::<u16>(&mut out_frame, &src_frames),
+            (SampleType::Integer, 17..=32) => self.sigma_clip::<u32>(&mut out_frame, &src_frames),
+            (SampleType::Float,        16) => self.sigma_clip::<f16>(&mut out_frame, &src_frames),
+            (SampleType::Float,        32) => self.sigma_clip::<f32>(&mut out_frame, &src_frames),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}
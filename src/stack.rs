@@ -0,0 +1,113 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, format_err};
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::{Framerate, Property, VideoInfo};
+use crate::common::*;
+use crate::property;
+use crate::temporal::reduce_frames;
+
+// weighting of the frames within a group, like how long a real shutter is open at each point of the exposure
+#[derive(Clone, Copy)]
+pub enum Shutter {
+    Box,
+    Triangle,
+    // raised cosine (Hann window)
+    Cosine,
+}
+
+impl Shutter {
+    // weight of each of `factor` frames, sampled at the centre of each frame's time slice.
+    // `None` for the box shutter, which doesn't weigh the frames at all.
+    pub fn weights(self, factor: usize) -> Option<Vec<f64>> {
+        let position = |i: usize| (i as f64 + 0.5) / factor as f64;
+        match self {
+            Shutter::Box => None,
+            Shutter::Triangle => Some((0..factor).map(|i| 1.0 - (2.0 * position(i) - 1.0).abs()).collect()),
+            Shutter::Cosine => Some((0..factor).map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * position(i)).cos()).collect()),
+        }
+    }
+}
+
+pub struct Stack<'core> {
+    pub clip: Node<'core>,
+    // number of consecutive input frames per output frame
+    pub factor: usize,
+    // per frame weights within a group
+    pub weights: Option<Vec<f64>>,
+    // trimming is limited at runtime so that at least one value remains, as the last group can be shorter
+    pub reducer: Reducer,
+}
+
+impl<'core> Stack<'core> {
+    // input frames making up output frame `n`. The last group is cut short at the end of the clip.
+    fn group(&self, n: usize) -> std::ops::Range<usize> {
+        let num_frames = property!(self.clip.info().num_frames);
+        n * self.factor..((n + 1) * self.factor).min(num_frames)
+    }
+}
+
+impl<'core> Filter<'core> for Stack<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        let info = self.clip.info();
+        let framerate = property!(info.framerate);
+
+        // the framerate is divided by `factor`, reduced to keep it from growing with every `Stack`
+        let (numerator, denominator) = (framerate.numerator, framerate.denominator * self.factor as u64);
+        let gcd = {
+            let (mut a, mut b) = (numerator, denominator);
+            while b != 0 {
+                let t = a % b;
+                a = b;
+                b = t;
+            }
+            a
+        };
+        let framerate = Framerate { numerator: numerator / gcd, denominator: denominator / gcd };
+
+        let num_frames = property!(info.num_frames).div_ceil(self.factor);
+        vec![VideoInfo { framerate: Property::Constant(framerate), num_frames: Property::Constant(num_frames), ..info }]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.group(n).for_each(|m| self.clip.request_frame_filter(context, m));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clip.info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.group(n)
+            .map(|m| self.clip.get_frame_filter(context, m).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let weights = self.weights.as_ref().map(|w| &w[..src_frames.len()]);
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        // each output frame lasts as long as the input frames it was made from
+        if let Ok(duration) = src_frames[0].props().get::<i64>("_DurationNum") {
+            out_frame.props_mut().set_int("_DurationNum", duration * src_frames.len() as i64)?;
+        }
+
+        reduce_frames(&mut out_frame, &src_frames, weights, self.reducer)?;
+
+        Ok(out_frame.into())
+    }
+}
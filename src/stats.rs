@@ -0,0 +1,183 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::format::Format;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::{Property, VideoInfo};
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+// Output order: mean, standard deviation, min, max, median, range
+const OUTPUTS: usize = 6;
+const STDDEV: usize = 1;
+const RANGE: usize = 5;
+
+// VapourSynth requests every output separately, but all of them are calculated in a single pass.
+// The outputs that weren't requested yet are kept around for when they are, for up to this many frames.
+// This is outside of the core's cache limit, and outputs the script never uses only go once they're evicted,
+// so it's kept small.
+const CACHE_SIZE: usize = 4;
+// Outputs of frames further away than this from the one being calculated are dropped,
+// as they're unlikely to still be requested.
+const CACHE_DISTANCE: usize = 2;
+
+pub struct Stats<'core> {
+    clips: Vec<Node<'core>>,
+    // format of the standard deviation and range outputs
+    spread_format: Format<'core>,
+    // frame number, and the outputs for it that haven't been requested yet
+    cache: Mutex<VecDeque<(usize, Vec<Option<FrameRef<'core>>>)>>,
+}
+
+impl<'core> Stats<'core> {
+    pub fn new(clips: Vec<Node<'core>>, spread_format: Format<'core>) -> Self {
+        Self { clips, spread_format, cache: Mutex::new(VecDeque::with_capacity(CACHE_SIZE)) }
+    }
+
+    // `S` is the sample type of the standard deviation and range outputs, `T` the one of everything else
+    pub fn stats<T: F64Convertible, S: F64Convertible>(out_frames: &mut [FrameRefMut], src_frames: &[FrameRef]) {
+        // See note on reusing vecs in mean.rs
+        let mut src_rows = Vec::with_capacity(src_frames.len());
+        let mut values = Vec::with_capacity(src_frames.len());
+        let reciprocal = 1.0 / src_frames.len() as f64;
+
+        let format = src_frames[0].format();
+
+        if let [mean, stddev, min, max, median, range] = out_frames {
+            for plane in 0..format.plane_count() {
+                for row in 0..src_frames[0].height(plane) {
+                    // Vec reuse: filling
+                    src_rows.extend(src_frames
+                        .iter()
+                        .map(|f| f.plane_row::<T>(plane, row)));
+
+                    let mean_row = mean.plane_row_mut::<T>(plane, row);
+                    let stddev_row = stddev.plane_row_mut::<S>(plane, row);
+                    let min_row = min.plane_row_mut::<T>(plane, row);
+                    let max_row = max.plane_row_mut::<T>(plane, row);
+                    let median_row = median.plane_row_mut::<T>(plane, row);
+                    let range_row = range.plane_row_mut::<S>(plane, row);
+
+                    for i in 0..mean_row.len() {
+                        // Vec reuse: filling
+                        values.extend(src_rows
+                            .iter()
+                            .map(|f| f[i].to_f64()));
+
+                        let mean = values.iter().sum::<f64>() * reciprocal;
+                        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() * reciprocal;
+                        let (min, max) = min_max(values.iter().copied());
+                        let median = crate::common::median(&mut values);
+
+                        unsafe {
+                            std::ptr::write(&mut mean_row[i], F64Convertible::from_f64(mean));
+                            std::ptr::write(&mut stddev_row[i], F64Convertible::from_f64(variance.sqrt()));
+                            std::ptr::write(&mut min_row[i], F64Convertible::from_f64(min));
+                            std::ptr::write(&mut max_row[i], F64Convertible::from_f64(max));
+                            std::ptr::write(&mut median_row[i], F64Convertible::from_f64(median));
+                            std::ptr::write(&mut range_row[i], F64Convertible::from_f64(max - min));
+                        }
+
+                        // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                        unsafe { values.set_len(0); }
+                    }
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                    unsafe { src_rows.set_len(0); }
+                }
+            }
+        }
+    }
+}
+
+impl<'core> Filter<'core> for Stats<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        let info = self.clips[0].info();
+        let spread_info = VideoInfo { format: Property::Constant(self.spread_format), ..info };
+        (0..OUTPUTS)
+            .map(|output| if output == STDDEV || output == RANGE { spread_info } else { info })
+            .collect()
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        // Even if the output was calculated already, it might get dropped from the cache before `get_frame`
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let output = context.output_index();
+
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(position) = cache.iter().position(|(m, outputs)| *m == n && outputs[output].is_some()) {
+                let frame = cache[position].1[output].take().unwrap();
+                if cache[position].1.iter().all(Option::is_none) {
+                    cache.remove(position);
+                }
+                return Ok(frame);
+            }
+        }
+
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frames = (0..OUTPUTS)
+            .map(|output| {
+                let format = if output == STDDEV || output == RANGE { self.spread_format } else { format };
+                unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) }
+            })
+            .collect::<Vec<_>>();
+
+        // the standard deviation and range are either in the input format, or in 32 bit float
+        let same = self.spread_format == format;
+        match (format.sample_type(), format.bits_per_sample(), same) {
+            (SampleType::Integer,       8, true)  => Self::stats::<u8,  u8> (&mut out_frames, &src_frames),
+            (SampleType::Integer,  9..=16, true)  => Self::stats::<u16, u16>(&mut out_frames, &src_frames),
+            (SampleType::Integer, 17..=32, true)  => Self::stats::<u32, u32>(&mut out_frames, &src_frames),
+            (SampleType::Integer,       8, false) => Self::stats::<u8,  f32>(&mut out_frames, &src_frames),
+            (SampleType::Integer,  9..=16, false) => Self::stats::<u16, f32>(&mut out_frames, &src_frames),
+            (SampleType::Integer, 17..=32, false) => Self::stats::<u32, f32>(&mut out_frames, &src_frames),
+            (SampleType::Float,        16, true)  => Self::stats::<f16, f16>(&mut out_frames, &src_frames),
+            (SampleType::Float,        16, false) => Self::stats::<f16, f32>(&mut out_frames, &src_frames),
+            (SampleType::Float,        32, _)     => Self::stats::<f32, f32>(&mut out_frames, &src_frames),
+            (sample_type, bits_per_sample, _) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        let mut out_frames = out_frames.into_iter().map(|f| Some(f.into())).collect::<Vec<Option<FrameRef>>>();
+        let frame = out_frames[output].take().unwrap();
+
+        let mut cache = self.cache.lock().unwrap();
+        // another thread might've calculated the same frame in the meantime
+        cache.retain(|(m, _)| *m != n && m.abs_diff(n) <= CACHE_DISTANCE);
+        if cache.len() == CACHE_SIZE {
+            cache.pop_front();
+        }
+        cache.push_back((n, out_frames));
+
+        Ok(frame)
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::mean::{Discard, Mean, MeanKind};
+use crate::median::{Median, MedianMode};
+use crate::{PLUGIN_NAME, property};
+
+// what to do with the part of the window that falls outside of the clip
+#[derive(Clone, Copy)]
+pub enum Boundary {
+    // reflect it back into the clip, without repeating the edge frame
+    Mirror,
+    // repeat the edge frame
+    Clamp,
+    // leave it out, so the window gets smaller
+    Shrink,
+}
+
+pub struct Temporal<'core> {
+    // the window is taken from every clip, with scene changes only being detected on the first one
+    pub clips: Vec<Node<'core>>,
+    pub radius: usize,
+    // per frame weights, from `n - radius` to `n + radius`, shared by every clip
+    pub weights: Option<Vec<f64>>,
+    pub boundary: Boundary,
+    // trimming is limited at runtime so that at least one value remains, as the window can shrink
+    pub reducer: Reducer,
+    // stop the window at scene changes, handling it like the start or end of the clip
+    pub scene_change: bool,
+    // normalised mean absolute difference of the first plane, above which two frames without scene change props are a cut
+    pub threshold: f64,
+}
+
+impl<'core> Temporal<'core> {
+    // The frames making up the window around `n`, paired with the index of their weight.
+    // `first..=last` is the range of frames the window may use.
+    fn window(&self, n: usize, first: usize, last: usize) -> Vec<(usize, usize)> {
+        let len = last - first + 1;
+        (0..=2 * self.radius)
+            .filter_map(|index| {
+                let m = (n + index) as isize - (self.radius + first) as isize;
+                let inside = m >= 0 && m < len as isize;
+                let m = match self.boundary {
+                    _ if inside => m as usize,
+                    Boundary::Mirror => mirror(m, len),
+                    Boundary::Clamp => m.max(0).min(len as isize - 1) as usize,
+                    Boundary::Shrink => return None,
+                };
+                Some((first + m, index))
+            })
+            .collect()
+    }
+}
+
+impl<'core> Filter<'core> for Temporal<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        // every frame of the window might be needed, either for the scene change detection or the window itself
+        let num_frames = property!(self.clips[0].info().num_frames);
+        for m in n.saturating_sub(self.radius)..=(n + self.radius).min(num_frames - 1) {
+            self.clips.iter().for_each(|f| f.request_frame_filter(context, m));
+        }
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+        let num_frames = property!(info.num_frames);
+
+        let (start, end) = (n.saturating_sub(self.radius), (n + self.radius).min(num_frames - 1));
+        // frames[m - start][clip]
+        let frames = (start..=end)
+            .map(|m| self.clips.iter()
+                .map(|f| f.get_frame_filter(context, m).ok_or_else(|| format_err!("Could not retrieve source frame")))
+                .collect::<Result<Vec<_>, _>>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // the range of frames in the same scene as `n`
+        let (mut first, mut last) = (0, num_frames - 1);
+        if self.scene_change {
+            first = n;
+            while first > start && !is_cut(&frames[first - 1 - start][0], &frames[first - start][0], self.threshold)? {
+                first -= 1;
+            }
+            last = n;
+            while last < end && !is_cut(&frames[last - start][0], &frames[last + 1 - start][0], self.threshold)? {
+                last += 1;
+            }
+        }
+
+        // mirrored and clamped frames never leave `start..=end`, as they're at most `radius` frames away from `n`
+        let window = self.window(n, first, last);
+        let src_frames = window.iter().flat_map(|&(m, _)| frames[m - start].iter().cloned()).collect::<Vec<_>>();
+        let weights = self.weights.as_ref().map(|w| window.iter()
+            .flat_map(|&(_, index)| std::iter::repeat_n(w[index], self.clips.len()))
+            .collect::<Vec<_>>());
+
+        // props are taken from the current frame of the first clip, not the first frame of the window
+        let prop_src = Some(&*frames[n - start][0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        reduce_frames(&mut out_frame, &src_frames, weights.as_deref(), self.reducer)?;
+
+        Ok(out_frame.into())
+    }
+}
+
+// Reduces `src_frames` into `out_frame` with the kernels of `Mean` and `Median`, optionally weighted.
+// The integer means use the wide sums, as there can be any number of frames.
+// Trimming is limited so that at least one value remains.
+pub fn reduce_frames(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], weights: Option<&[f64]>, reducer: Reducer) -> Result<(), Error> {
+    // `out_frame` has the same format as the input clips
+    let format = out_frame.format();
+
+    match (reducer, weights) {
+        (Reducer::Mean, Some(weights)) => match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Mean::weighted_mean::<u8> (out_frame, src_frames, weights, MeanKind::Arithmetic),
+            (SampleType::Integer,  9..=16) => Mean::weighted_mean::<u16>(out_frame, src_frames, weights, MeanKind::Arithmetic),
+            (SampleType::Integer, 17..=32) => Mean::weighted_mean::<u32>(out_frame, src_frames, weights, MeanKind::Arithmetic),
+            (SampleType::Float,        16) => Mean::weighted_mean::<f16>(out_frame, src_frames, weights, MeanKind::Arithmetic),
+            (SampleType::Float,        32) => Mean::weighted_mean::<f32>(out_frame, src_frames, weights, MeanKind::Arithmetic),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        },
+        (Reducer::Mean, None) => match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Mean::mean_u8_wide (out_frame, src_frames),
+            (SampleType::Integer,  9..=16) => Mean::mean_u16_wide(out_frame, src_frames),
+            (SampleType::Integer, 17..=32) => Mean::mean_u32(out_frame, src_frames),
+            (SampleType::Float,        16) => Mean::mean_float::<f16>(out_frame, src_frames),
+            (SampleType::Float,        32) => Mean::mean_float::<f32>(out_frame, src_frames),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        },
+        (Reducer::Trimmed(discard), weights) => {
+            let discard = discard.min((src_frames.len() - 1) / 2);
+            let discard = Discard { low: discard, high: discard, winsorize: false };
+            match (weights, format.sample_type(), format.bits_per_sample()) {
+                (Some(weights), SampleType::Integer,       8) => Mean::weighted_mean_discard::<u8> (out_frame, src_frames, weights, discard, MeanKind::Arithmetic),
+                (Some(weights), SampleType::Integer,  9..=16) => Mean::weighted_mean_discard::<u16>(out_frame, src_frames, weights, discard, MeanKind::Arithmetic),
+                (Some(weights), SampleType::Integer, 17..=32) => Mean::weighted_mean_discard::<u32>(out_frame, src_frames, weights, discard, MeanKind::Arithmetic),
+                (Some(weights), SampleType::Float,        16) => Mean::weighted_mean_discard::<f16>(out_frame, src_frames, weights, discard, MeanKind::Arithmetic),
+                (Some(weights), SampleType::Float,        32) => Mean::weighted_mean_discard::<f32>(out_frame, src_frames, weights, discard, MeanKind::Arithmetic),
+                (None, SampleType::Integer,       8) => Mean::mean_u8_wide_discard(out_frame, src_frames, discard),
+                (None, SampleType::Integer,  9..=16) => Mean::mean_u16_wide_discard(out_frame, src_frames, discard),
+                (None, SampleType::Integer, 17..=32) => Mean::mean_u32_discard(out_frame, src_frames, discard),
+                (None, SampleType::Float,        16) => Mean::mean_float_discard::<f16>(out_frame, src_frames, discard),
+                (None, SampleType::Float,        32) => Mean::mean_float_discard::<f32>(out_frame, src_frames, discard),
+                (_, sample_type, bits_per_sample) =>
+                    bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+            }
+        },
+        (Reducer::Median, Some(weights)) => match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Median::weighted_median::<u8> (out_frame, src_frames, weights, MedianMode::Mean),
+            (SampleType::Integer,  9..=16) => Median::weighted_median::<u16>(out_frame, src_frames, weights, MedianMode::Mean),
+            (SampleType::Integer, 17..=32) => Median::weighted_median::<u32>(out_frame, src_frames, weights, MedianMode::Mean),
+            (SampleType::Float,        16) => Median::weighted_median::<f16>(out_frame, src_frames, weights, MedianMode::Mean),
+            (SampleType::Float,        32) => Median::weighted_median::<f32>(out_frame, src_frames, weights, MedianMode::Mean),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        },
+        (Reducer::Median, None) => match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Median::median_u8 (out_frame, src_frames, MedianMode::Mean),
+            (SampleType::Integer,  9..=16) => Median::median_u16(out_frame, src_frames, MedianMode::Mean),
+            (SampleType::Integer, 17..=32) => Median::median_u32(out_frame, src_frames, MedianMode::Mean),
+            (SampleType::Float,        16) => Median::median_float::<f16>(out_frame, src_frames, MedianMode::Mean),
+            (SampleType::Float,        32) => Median::median_float::<f32>(out_frame, src_frames, MedianMode::Mean),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        },
+    }
+
+    Ok(())
+}
+
+// Mean absolute difference of the first plane of two frames
+fn difference<T: F64Convertible>(a: &FrameRef, b: &FrameRef) -> f64 {
+    let sum: f64 = (0..a.height(0))
+        .map(|row| a.plane_row::<T>(0, row)
+            .iter()
+            .zip(b.plane_row::<T>(0, row))
+            .map(|(a, b)| (a.to_f64() - b.to_f64()).abs())
+            .sum::<f64>())
+        .sum();
+    sum / (a.width(0) * a.height(0)) as f64
+}
+
+// Whether there is a scene change between two consecutive frames.
+// The props of either frame decide it if present, otherwise whether their normalised difference is above `threshold`.
+pub fn is_cut(prev: &FrameRef, next: &FrameRef, threshold: f64) -> Result<bool, Error> {
+    let prop = |f: &FrameRef, key| f.props().get::<i64>(key).ok().map(|v| v != 0);
+    match (prop(next, "_SceneChangePrev"), prop(prev, "_SceneChangeNext")) {
+        (None, None) => (),
+        (a, b) => return Ok(a.unwrap_or(false) || b.unwrap_or(false)),
+    }
+
+    let format = prev.format();
+    let difference = match (format.sample_type(), format.bits_per_sample()) {
+        (SampleType::Integer,       8) => difference::<u8> (prev, next),
+        (SampleType::Integer,  9..=16) => difference::<u16>(prev, next),
+        (SampleType::Integer, 17..=32) => difference::<u32>(prev, next),
+        (SampleType::Float,        16) => difference::<f16>(prev, next),
+        (SampleType::Float,        32) => difference::<f32>(prev, next),
+        (sample_type, bits_per_sample) =>
+            bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+    };
+    let peak = match format.sample_type() {
+        SampleType::Integer => ((1u64 << format.bits_per_sample()) - 1) as f64,
+        SampleType::Float => 1.0,
+    };
+    Ok(difference / peak > threshold)
+}
@@ -0,0 +1,99 @@
+// Copyright (c) EoE & Nephren 2020-2021. All rights reserved.
+
+use failure::{Error, bail, format_err};
+use half::f16;
+use vapoursynth::prelude::*;
+use vapoursynth::core::CoreRef;
+use vapoursynth::plugins::{Filter, FrameContext};
+use vapoursynth::video_info::VideoInfo;
+use crate::common::*;
+use crate::{PLUGIN_NAME, property};
+
+pub struct WindowMedian<'core> {
+    pub clips: Vec<Node<'core>>,
+    pub radius: usize,
+}
+
+impl<'core> WindowMedian<'core> {
+    pub fn window_median<T: F64Convertible>(out_frame: &mut FrameRefMut, src_frames: &[FrameRef], radius: usize) {
+        let diameter = 2 * radius + 1;
+
+        // See note on reusing vecs in mean.rs
+        let mut src_rows = Vec::with_capacity(diameter * src_frames.len());
+        let mut values = Vec::with_capacity(diameter * diameter * src_frames.len());
+
+        // `out_frame` has the same format as the input clips
+        let format = out_frame.format();
+
+        for plane in 0..format.plane_count() {
+            let (width, height) = (out_frame.width(plane), out_frame.height(plane));
+            for row in 0..height {
+                // Vec reuse: filling
+                // every row of the window, from every clip
+                src_rows.extend((0..diameter)
+                    .map(|y| mirror(row as isize + y as isize - radius as isize, height))
+                    .flat_map(|y| src_frames.iter().map(move |f| f.plane_row::<T>(plane, y))));
+                for (i, pixel) in out_frame.plane_row_mut::<T>(plane, row).iter_mut().enumerate() {
+                    // Vec reuse: filling
+                    values.extend((0..diameter)
+                        .map(|x| mirror(i as isize + x as isize - radius as isize, width))
+                        .flat_map(|x| src_rows.iter().map(move |f| f[x].to_f64())));
+                    let data = median(&mut values);
+                    unsafe { std::ptr::write(pixel, F64Convertible::from_f64(data)) }
+                    // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                    unsafe { values.set_len(0); }
+                }
+                // Vec reuse: (unsafe) clearing; see `set_len` SAFETY in mean.rs
+                unsafe { src_rows.set_len(0); }
+            }
+        }
+    }
+}
+
+impl<'core> Filter<'core> for WindowMedian<'core> {
+    fn video_info(&self, _: API, _: CoreRef<'core>) -> Vec<VideoInfo<'core>> {
+        vec![self.clips[0].info()]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _: API,
+        _: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        self.clips.iter().for_each(|f| f.request_frame_filter(context, n));
+        Ok(None)
+    }
+
+    fn get_frame(
+        &self,
+        _: API,
+        core: CoreRef<'core>,
+        context: FrameContext,
+        n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        let info = self.clips[0].info();
+        let format = property!(info.format);
+        let resolution = property!(info.resolution);
+
+        let src_frames = self.clips.iter()
+            .map(|f| f.get_frame_filter(context, n).ok_or_else(|| format_err!("Could not retrieve source frame")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prop_src = Some(&*src_frames[0]);
+        let mut out_frame = unsafe { FrameRefMut::new_uninitialized(core, prop_src, format, resolution) };
+
+        match (format.sample_type(), format.bits_per_sample()) {
+            (SampleType::Integer,       8) => Self::window_median::<u8> (&mut out_frame, &src_frames, self.radius),
+            (SampleType::Integer,  9..=16) => Self::window_median::<u16>(&mut out_frame, &src_frames, self.radius),
+            (SampleType::Integer, 17..=32) => Self::window_median::<u32>(&mut out_frame, &src_frames, self.radius),
+            (SampleType::Float,        16) => Self::window_median::<f16>(&mut out_frame, &src_frames, self.radius),
+            (SampleType::Float,        32) => Self::window_median::<f32>(&mut out_frame, &src_frames, self.radius),
+            (sample_type, bits_per_sample) =>
+                bail!("{}: input depth {} not supported for sample type {}", PLUGIN_NAME, bits_per_sample, sample_type),
+        }
+
+        Ok(out_frame.into())
+    }
+}